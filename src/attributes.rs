@@ -0,0 +1,379 @@
+use serde::{Deserialize, Serialize};
+
+use crate::obj_wc::{normalize_term, ObjWooCommerce, ProductAttribute, WC_PER_PAGE};
+use crate::retry::Idempotency;
+use crate::wc_error::{self, WcError};
+
+// the global attributes vendoo fields end up in, see LocalObject::attributes
+pub const BRAND_ATTRIBUTE: &str = "Brand";
+pub const CONDITION_ATTRIBUTE: &str = "Condition";
+pub const COLOR_ATTRIBUTE: &str = "Color";
+
+// a global attribute from /products/attributes (a pa_* taxonomy in WordPress)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WcAttribute {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub slug: String,
+    #[serde(skip)]
+    pub terms: Option<Vec<WcAttributeTerm>>, // fetched the first time a product needs them
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WcAttributeTerm {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub slug: String,
+}
+
+// the store's global attributes and their terms, grown as they get created
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AttributeCache {
+    pub attributes: Vec<WcAttribute>,
+}
+
+impl AttributeCache {
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let name = normalize_term(name);
+        self.attributes
+            .iter()
+            .position(|attribute| normalize_term(&attribute.name) == name)
+    }
+}
+
+impl ObjWooCommerce {
+    pub async fn fetch_attributes(&self) -> Result<Vec<WcAttribute>, WcError> {
+        let url = self.endpoint("products/attributes");
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::Idempotent, || client.get(&url))
+            .await?;
+
+        let attributes: Vec<WcAttribute> =
+            wc_error::read_json(response, "fetch attributes").await?;
+        Ok(attributes)
+    }
+
+    // every page of /products/attributes/{id}/terms
+    pub async fn fetch_attribute_terms(
+        &self,
+        attribute_id: u64,
+    ) -> Result<Vec<WcAttributeTerm>, WcError> {
+        let url = self.endpoint(&format!("products/attributes/{}/terms", attribute_id));
+        let client = &self.client;
+        let mut terms: Vec<WcAttributeTerm> = Vec::new();
+        let mut page: u32 = 1;
+
+        loop {
+            let response = self
+                .send(Idempotency::Idempotent, || {
+                    client
+                        .get(&url)
+                        .query(&[("per_page", WC_PER_PAGE), ("page", page)])
+                })
+                .await?;
+
+            let context = format!("fetch terms of attribute {} (page {})", attribute_id, page);
+            let mut batch: Vec<WcAttributeTerm> = wc_error::read_json(response, context).await?;
+            let batch_len = batch.len();
+            terms.append(&mut batch);
+            if batch_len < WC_PER_PAGE as usize {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(terms)
+    }
+
+    // has_archives gives the attribute its own archive pages, which is what the layered
+    // navigation widgets filter through
+    pub async fn create_attribute(&self, name: &str) -> Result<WcAttribute, WcError> {
+        let url = self.endpoint("products/attributes");
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::IfNotProcessed, || {
+                client.post(&url).json(&serde_json::json!({
+                    "name": name,
+                    "type": "select",
+                    "order_by": "menu_order",
+                    "has_archives": true,
+                }))
+            })
+            .await?;
+
+        let context = format!("create attribute {}", name);
+        let attribute: WcAttribute = wc_error::read_json(response, context).await?;
+        Ok(attribute)
+    }
+
+    pub async fn create_attribute_term(
+        &self,
+        attribute_id: u64,
+        name: &str,
+    ) -> Result<WcAttributeTerm, WcError> {
+        let url = self.endpoint(&format!("products/attributes/{}/terms", attribute_id));
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::Idempotent, || {
+                client.post(&url).json(&serde_json::json!({ "name": name }))
+            })
+            .await?;
+
+        let context = format!("create term {}", name);
+        let error = match wc_error::read_json(response, context).await {
+            Ok(term) => return Ok(term),
+            Err(e) => e,
+        };
+        // same as categories, an existing term comes back as an error carrying its id
+        if let (Some("term_exists"), Some(id)) = (error.api_code(), error.resource_id()) {
+            return Ok(WcAttributeTerm {
+                id,
+                name: name.to_owned(),
+                slug: String::new(),
+            });
+        }
+        Err(error)
+    }
+
+    // the global attribute called name with every option present as a term, creating
+    // whatever is missing. returns the attribute id.
+    pub async fn resolve_attribute(
+        &mut self,
+        name: &str,
+        options: &[String],
+    ) -> Result<u64, WcError> {
+        if self.attributes.is_none() {
+            self.attributes = Some(AttributeCache {
+                attributes: self.fetch_attributes().await?,
+            });
+        }
+
+        let idx = match self.attributes.as_ref().unwrap().find(name) {
+            Some(idx) => idx,
+            None => {
+                let created = self.create_attribute(name).await?;
+                println!(
+                    "[] created WooCommerce attribute {} (#{})",
+                    name, created.id
+                );
+                let cache = self.attributes.as_mut().unwrap();
+                cache.attributes.push(created);
+                cache.attributes.len() - 1
+            }
+        };
+        let attribute_id = self.attributes.as_ref().unwrap().attributes[idx].id;
+
+        if self.attributes.as_ref().unwrap().attributes[idx]
+            .terms
+            .is_none()
+        {
+            let terms = self.fetch_attribute_terms(attribute_id).await?;
+            self.attributes.as_mut().unwrap().attributes[idx].terms = Some(terms);
+        }
+
+        for option in options {
+            let exists = self.attributes.as_ref().unwrap().attributes[idx]
+                .terms
+                .as_ref()
+                .unwrap()
+                .iter()
+                .any(|term| normalize_term(&term.name) == normalize_term(option));
+            if exists {
+                continue;
+            }
+            let term = self.create_attribute_term(attribute_id, option).await?;
+            println!("[] created {} term {} (#{})", name, option, term.id);
+            self.attributes.as_mut().unwrap().attributes[idx]
+                .terms
+                .as_mut()
+                .unwrap()
+                .push(term);
+        }
+
+        Ok(attribute_id)
+    }
+
+    // gives every name-only attribute LocalObject::attributes produced its global id, so
+    // WooCommerce links the product to the pa_* terms instead of a custom text attribute
+    pub async fn resolve_product_attributes(
+        &mut self,
+        attributes: &mut [ProductAttribute],
+    ) -> Result<(), WcError> {
+        for attribute in attributes.iter_mut() {
+            if attribute.id.is_some() || attribute.options.is_empty() {
+                continue;
+            }
+            let id = self
+                .resolve_attribute(&attribute.name, &attribute.options)
+                .await?;
+            attribute.id = Some(id);
+        }
+        Ok(())
+    }
+
+    // same thing for the "attributes" array of an update patch
+    pub async fn resolve_patch_attributes(
+        &mut self,
+        patch: &mut serde_json::Value,
+    ) -> Result<(), WcError> {
+        let attributes = match patch.get("attributes") {
+            Some(attributes) => attributes.clone(),
+            None => return Ok(()),
+        };
+        let mut attributes: Vec<ProductAttribute> =
+            wc_error::from_value(attributes, "patch attributes")?;
+        self.resolve_product_attributes(&mut attributes).await?;
+        patch["attributes"] = serde_json::json!(attributes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::local::LocalObject;
+    use crate::obj_vd::VendooProduct;
+    use crate::obj_wc::WooCommerceProduct;
+
+    fn vendoo(fields: serde_json::Value) -> LocalObject {
+        let mut row = serde_json::json!({"Sku": "A-1", "Title": "Jacket", "Status": "Active"});
+        row.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        let product: VendooProduct = serde_json::from_value(row).unwrap();
+        LocalObject::from_vendoo_object(&product)
+    }
+
+    fn attribute(id: u64, name: &str, terms: Option<&[&str]>) -> WcAttribute {
+        WcAttribute {
+            id,
+            name: String::from(name),
+            slug: String::new(),
+            terms: terms.map(|terms| {
+                terms
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, term)| WcAttributeTerm {
+                        id: 100 + idx as u64,
+                        name: String::from(*term),
+                        slug: String::new(),
+                    })
+                    .collect()
+            }),
+        }
+    }
+
+    #[test]
+    fn vendoo_fields_become_attributes() {
+        let object = vendoo(serde_json::json!({
+            "Brand": " Levi's ",
+            "Condition": "Like New",
+            "Primary Color": "Blue",
+            "Secondary Color": "",
+        }));
+        let attributes = object.attributes();
+
+        let names: Vec<&str> = attributes.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![BRAND_ATTRIBUTE, CONDITION_ATTRIBUTE, COLOR_ATTRIBUTE]
+        );
+        assert_eq!(attributes[0].options, vec!["Levi's"]);
+        assert_eq!(attributes[1].options, vec!["Like New"]);
+        assert_eq!(attributes[2].options, vec!["Blue"]);
+        for (position, attribute) in attributes.iter().enumerate() {
+            assert_eq!(attribute.position, position as u32);
+            assert!(attribute.id.is_none());
+            assert!(attribute.visible);
+            assert!(!attribute.variation);
+        }
+    }
+
+    #[test]
+    fn empty_fields_are_left_out() {
+        let object = vendoo(serde_json::json!({
+            "Brand": "  ",
+            "Primary Color": "Red",
+            "Secondary Color": "Black",
+        }));
+        let attributes = object.attributes();
+
+        assert_eq!(attributes.len(), 1);
+        assert_eq!(attributes[0].name, COLOR_ATTRIBUTE);
+        assert_eq!(attributes[0].position, 0);
+        assert_eq!(attributes[0].options, vec!["Red", "Black"]);
+    }
+
+    #[test]
+    fn attributes_read_back_from_woocommerce() {
+        let product: WooCommerceProduct = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "Jacket",
+            "regular_price": "10.00",
+            "description": "",
+            "categories": [],
+            "images": [],
+            "stock_quantity": null,
+            "status": "publish",
+            "sku": "A-1",
+            "attributes": [
+                {"id": 3, "name": "brand", "options": ["Levi's"]},
+                {"id": 5, "name": "Color", "options": ["Blue", "Black"]},
+            ],
+        }))
+        .unwrap();
+        let object = LocalObject::from_woocommerce_object(&product);
+
+        assert_eq!(object.brand.as_deref(), Some("Levi's"));
+        assert_eq!(object.condition, None);
+        assert_eq!(object.colors, vec!["Blue", "Black"]);
+    }
+
+    #[test]
+    fn cache_finds_attributes_by_normalized_name() {
+        let cache = AttributeCache {
+            attributes: vec![attribute(1, "Brand", None), attribute(2, "Color", None)],
+        };
+        assert_eq!(cache.find("color"), Some(1));
+        assert_eq!(cache.find("  BRAND "), Some(0));
+        assert_eq!(cache.find("Condition"), None);
+    }
+
+    #[tokio::test]
+    async fn existing_terms_are_reused() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products/attributes/7/terms"))
+            .and(body_json(serde_json::json!({"name": "Fair"})))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": 9,
+                "name": "Fair",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut wc =
+            ObjWooCommerce::new_with_auth(server.uri(), String::from("ck"), String::from("cs"));
+        wc.attributes = Some(AttributeCache {
+            attributes: vec![attribute(7, "Condition", Some(&["Like  New"]))],
+        });
+
+        let options = vec![String::from("like new"), String::from("Fair")];
+        let id = wc.resolve_attribute("condition", &options).await.unwrap();
+        assert_eq!(id, 7);
+        let terms = wc.attributes.unwrap().attributes[0].terms.clone().unwrap();
+        assert_eq!(terms.len(), 2);
+        assert_eq!(terms[1].id, 9);
+    }
+}
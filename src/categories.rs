@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+use crate::obj_wc::{decode_entities, Category, ObjWooCommerce, WooCommerceProduct, WC_PER_PAGE};
+use crate::retry::Idempotency;
+use crate::wc_error::{self, WcError};
+
+// vendoo writes nested categories as "Men > Tops > T-Shirts"
+pub const CATEGORY_PATH_SEPARATOR: char = '>';
+
+// a term from /products/categories
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WcCategory {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub slug: String,
+    #[serde(default)]
+    pub parent: u64, // 0 for top level
+}
+
+// the store's category tree, fetched once and grown as categories get created
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CategoryTree {
+    pub categories: Vec<WcCategory>,
+}
+
+impl CategoryTree {
+    pub fn find(&self, parent: u64, name: &str) -> Option<&WcCategory> {
+        let name = normalize_category_name(name);
+        self.categories.iter().find(|category| {
+            category.parent == parent && normalize_category_name(&category.name) == name
+        })
+    }
+}
+
+impl ObjWooCommerce {
+    // every page of /products/categories
+    pub async fn fetch_categories(&self) -> Result<Vec<WcCategory>, WcError> {
+        let url = self.endpoint("products/categories");
+        let client = &self.client;
+        let mut categories: Vec<WcCategory> = Vec::new();
+        let mut page: u32 = 1;
+
+        loop {
+            let response = self
+                .send(Idempotency::Idempotent, || {
+                    client
+                        .get(&url)
+                        .query(&[("per_page", WC_PER_PAGE), ("page", page)])
+                })
+                .await?;
+
+            let context = format!("fetch categories (page {})", page);
+            let mut batch: Vec<WcCategory> = wc_error::read_json(response, context).await?;
+            let batch_len = batch.len();
+            categories.append(&mut batch);
+            if batch_len < WC_PER_PAGE as usize {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(categories)
+    }
+
+    pub async fn create_category(&self, name: &str, parent: u64) -> Result<WcCategory, WcError> {
+        let url = self.endpoint("products/categories");
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::Idempotent, || {
+                client
+                    .post(&url)
+                    .json(&serde_json::json!({ "name": name, "parent": parent }))
+            })
+            .await?;
+
+        let context = format!("create category {}", name);
+        let error = match wc_error::read_json(response, context).await {
+            Ok(category) => return Ok(category),
+            Err(e) => e,
+        };
+        // someone beat us to it (or the cache is stale), WooCommerce hands back the id
+        if let (Some("term_exists"), Some(id)) = (error.api_code(), error.resource_id()) {
+            return Ok(WcCategory {
+                id,
+                name: name.to_owned(),
+                slug: String::new(),
+                parent,
+            });
+        }
+        Err(error)
+    }
+
+    // walks "Men > Tops > T-Shirts" down the cached tree, creating whatever level is
+    // missing, and returns the id of the deepest category
+    pub async fn resolve_category_path(&mut self, path: &str) -> Result<Option<u64>, WcError> {
+        if self.categories.is_none() {
+            self.categories = Some(CategoryTree {
+                categories: self.fetch_categories().await?,
+            });
+        }
+
+        let mut parent: u64 = 0;
+        for name in path.split(CATEGORY_PATH_SEPARATOR) {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+
+            let existing = self
+                .categories
+                .as_ref()
+                .unwrap()
+                .find(parent, name)
+                .map(|category| category.id);
+
+            parent = match existing {
+                Some(id) => id,
+                None => {
+                    let created = self.create_category(name, parent).await?;
+                    println!("[] created WooCommerce category {} (#{})", name, created.id);
+                    let id = created.id;
+                    self.categories.as_mut().unwrap().categories.push(created);
+                    id
+                }
+            };
+        }
+
+        Ok(if parent == 0 { None } else { Some(parent) })
+    }
+
+    // swaps the name-only categories LocalObject::to_woocommerce_object produces for ids,
+    // which is all the WooCommerce create/update API actually reads
+    pub async fn resolve_product_categories(
+        &mut self,
+        product: &mut WooCommerceProduct,
+    ) -> Result<(), WcError> {
+        let mut resolved: Vec<Category> = Vec::new();
+
+        for category in product.categories.drain(..).collect::<Vec<Category>>() {
+            if category.id.is_some() {
+                resolved.push(category);
+                continue;
+            }
+            if let Some(id) = self.resolve_category_path(&category.name).await? {
+                if !resolved.iter().any(|existing| existing.id == Some(id)) {
+                    resolved.push(Category {
+                        id: Some(id),
+                        name: category.name,
+                    });
+                }
+            }
+        }
+
+        product.categories = resolved;
+        Ok(())
+    }
+}
+
+fn normalize_category_name(name: &str) -> String {
+    decode_entities(name).trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const CATEGORIES: &str = "/wp-json/wc/v3/products/categories";
+
+    async fn store(server: &MockServer, existing: serde_json::Value) -> ObjWooCommerce {
+        Mock::given(method("GET"))
+            .and(path(CATEGORIES))
+            .respond_with(ResponseTemplate::new(200).set_body_json(existing))
+            .expect(1)
+            .mount(server)
+            .await;
+        ObjWooCommerce::new_with_auth(server.uri(), String::from("ck"), String::from("cs"))
+    }
+
+    async fn creates(server: &MockServer, name: &str, parent: u64, response: ResponseTemplate) {
+        Mock::given(method("POST"))
+            .and(path(CATEGORIES))
+            .and(body_json(
+                serde_json::json!({"name": name, "parent": parent}),
+            ))
+            .respond_with(response)
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    fn created(id: u64, name: &str, parent: u64) -> ResponseTemplate {
+        ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "id": id,
+            "name": name,
+            "parent": parent,
+        }))
+    }
+
+    #[tokio::test]
+    async fn existing_levels_are_reused() {
+        let server = MockServer::start().await;
+        let mut wc = store(
+            &server,
+            serde_json::json!([
+                {"id": 1, "name": "Men", "parent": 0},
+                {"id": 2, "name": "Tops &amp; Tees", "parent": 1},
+                {"id": 9, "name": "Tops &amp; Tees", "parent": 0},
+            ]),
+        )
+        .await;
+        creates(&server, "T-Shirts", 2, created(3, "T-Shirts", 2)).await;
+
+        let id = wc
+            .resolve_category_path(" men >Tops & Tees>  T-Shirts ")
+            .await
+            .unwrap();
+        assert_eq!(id, Some(3));
+        // all cached now, nothing else goes out
+        let again = wc
+            .resolve_category_path("Men > Tops & Tees > T-Shirts")
+            .await
+            .unwrap();
+        assert_eq!(again, Some(3));
+        assert_eq!(wc.resolve_category_path(" > ").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn missing_parents_are_created_first() {
+        let server = MockServer::start().await;
+        let mut wc = store(&server, serde_json::json!([])).await;
+        creates(&server, "Women", 0, created(10, "Women", 0)).await;
+        // created by someone else since the tree was fetched
+        creates(
+            &server,
+            "Shoes",
+            10,
+            ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "code": "term_exists",
+                "message": "A term with the name provided already exists with this parent.",
+                "data": {"status": 400, "resource_id": 11},
+            })),
+        )
+        .await;
+        creates(&server, "Boots", 11, created(12, "Boots", 11)).await;
+
+        let id = wc
+            .resolve_category_path("Women > Shoes > Boots")
+            .await
+            .unwrap();
+        assert_eq!(id, Some(12));
+
+        let posted: Vec<String> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.method.as_str() == "POST")
+            .map(|request| {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                body["name"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        assert_eq!(posted, vec!["Women", "Shoes", "Boots"]);
+        let tree = wc.categories.unwrap();
+        assert!(tree.find(10, "shoes").is_some_and(|shoes| shoes.id == 11));
+    }
+}
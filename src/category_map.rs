@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use csv::ReaderBuilder;
+use serde::Deserialize;
+
+use crate::{categories::CATEGORY_PATH_SEPARATOR, local::LocalSession};
+
+// one line of the mapping file. vendoo is an exact path ("Men > Tops > T-Shirts"), a
+// pattern with * wildcards ("Men > Shoes > *", "* > Jeans") or just * for the fallback.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CategoryRule {
+    pub vendoo: String,
+    pub woocommerce: String,
+}
+
+// the user-editable vendoo -> WooCommerce category table, CATEGORY_MAP in .env:
+//
+//     vendoo,woocommerce
+//     # lines starting with # are ignored
+//     Men > Tops > T-Shirts,Clothing > Men > Tees
+//     Men > Shoes > *,Footwear > Men
+//     *,Uncategorized
+#[derive(Debug, Clone, Default)]
+pub struct CategoryMap {
+    pub rules: Vec<CategoryRule>,
+}
+
+// vendoo categories that matched no rule (the fallback may still have caught them), with
+// how many products had each
+#[derive(Debug, Clone, Default)]
+pub struct CategoryMapReport {
+    pub mapped: usize,
+    pub fallback: usize,
+    pub unmatched: BTreeMap<String, usize>,
+}
+
+impl CategoryMapReport {
+    pub fn debug(&self) -> String {
+        let mut str = format!(
+            "--- CATEGORY MAP ---\n{} mapped by a rule, {} by the fallback, {} categories unmatched\n",
+            self.mapped,
+            self.fallback,
+            self.unmatched.len()
+        );
+        for (category, n) in &self.unmatched {
+            str.push_str(&format!("    UNMATCHED: {} ({} products)\n", category, n));
+        }
+        str
+    }
+}
+
+enum RuleHit<'a> {
+    Rule(&'a str),
+    Fallback(&'a str),
+}
+
+impl CategoryMap {
+    pub fn from_csv(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(Path::new(path))?;
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(file);
+
+        let mut rules: Vec<CategoryRule> = Vec::new();
+        for result in rdr.deserialize() {
+            let rule: CategoryRule = result?;
+            if !rule.vendoo.is_empty() {
+                rules.push(rule);
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    // CATEGORY_MAP if it's set, an empty map (everything passes through) if it isn't
+    pub fn from_env() -> Self {
+        match std::env::var("CATEGORY_MAP") {
+            Ok(path) => match Self::from_csv(&path) {
+                Ok(map) => map,
+                Err(e) => {
+                    eprintln!("[] couldn't read CATEGORY_MAP {}: {}", path, e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // exact match first, then the wildcard rule with the most literal text, then "*"
+    fn lookup(&self, category: &str) -> Option<RuleHit<'_>> {
+        let category = normalize_path(category);
+        let mut best: Option<(&CategoryRule, usize)> = None;
+        let mut fallback: Option<&CategoryRule> = None;
+
+        for rule in &self.rules {
+            let pattern = normalize_path(&rule.vendoo);
+            if pattern == "*" {
+                fallback = fallback.or(Some(rule));
+                continue;
+            }
+            if pattern == category {
+                return Some(RuleHit::Rule(&rule.woocommerce));
+            }
+            if pattern.contains('*') && wildcard_match(&pattern, &category) {
+                let specificity = pattern.chars().filter(|c| *c != '*').count();
+                if best.is_none_or(|(_, best)| specificity > best) {
+                    best = Some((rule, specificity));
+                }
+            }
+        }
+
+        match (best, fallback) {
+            (Some((rule, _)), _) => Some(RuleHit::Rule(&rule.woocommerce)),
+            (None, Some(rule)) => Some(RuleHit::Fallback(&rule.woocommerce)),
+            (None, None) => None,
+        }
+    }
+
+    // rewrites the categories of every vendoo product in the session. categories no rule
+    // matches are left as they are (and reported) so they still get created on upload.
+    pub fn apply(&self, session: &mut LocalSession) -> CategoryMapReport {
+        let mut report = CategoryMapReport::default();
+        if self.is_empty() {
+            return report;
+        }
+
+        for vp_object in session.local_vp.iter_mut() {
+            let mut mapped: Vec<String> = Vec::new();
+            for category in vp_object.categories.split(',') {
+                let category = category.trim();
+                if category.is_empty() {
+                    continue;
+                }
+                let target = match self.lookup(category) {
+                    Some(RuleHit::Rule(target)) => {
+                        report.mapped += 1;
+                        target.to_owned()
+                    }
+                    Some(RuleHit::Fallback(target)) => {
+                        report.fallback += 1;
+                        *report.unmatched.entry(category.to_owned()).or_insert(0) += 1;
+                        target.to_owned()
+                    }
+                    None => {
+                        *report.unmatched.entry(category.to_owned()).or_insert(0) += 1;
+                        category.to_owned()
+                    }
+                };
+                if !target.is_empty() && !mapped.contains(&target) {
+                    mapped.push(target);
+                }
+            }
+            vp_object.categories = mapped.join(", ");
+        }
+
+        report
+    }
+}
+
+// "men>Tops >  T-Shirts" -> "men > tops > t-shirts"
+pub(crate) fn normalize_path(path: &str) -> String {
+    path.split(CATEGORY_PATH_SEPARATOR)
+        .map(|part| part.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .collect::<Vec<String>>()
+        .join(&format!(" {} ", CATEGORY_PATH_SEPARATOR))
+        .to_lowercase()
+}
+
+// * matches any run of characters, including none
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = text;
+
+    for (idx, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            continue;
+        }
+        if idx == 0 {
+            match rest.strip_prefix(part) {
+                Some(stripped) => rest = stripped,
+                None => return false,
+            }
+        } else if idx == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+
+    // a pattern ending in * swallows the rest, one ending in text must have used it all
+    pattern.ends_with('*') || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::local::LocalObject;
+    use crate::obj_vd::VendooProduct;
+
+    fn map(rules: &[(&str, &str)]) -> CategoryMap {
+        CategoryMap {
+            rules: rules
+                .iter()
+                .map(|(vendoo, woocommerce)| CategoryRule {
+                    vendoo: String::from(*vendoo),
+                    woocommerce: String::from(*woocommerce),
+                })
+                .collect(),
+        }
+    }
+
+    fn target(map: &CategoryMap, category: &str) -> Option<String> {
+        match map.lookup(category) {
+            Some(RuleHit::Rule(target)) => Some(format!("rule {}", target)),
+            Some(RuleHit::Fallback(target)) => Some(format!("fallback {}", target)),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("men > shoes > *", "men > shoes > boots"));
+        assert!(wildcard_match("* > jeans", "women > jeans"));
+        assert!(wildcard_match("men > * > tees", "men > tops > tees"));
+        assert!(wildcard_match("men*", "men"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("men > shoes > *", "women > shoes > boots"));
+        assert!(!wildcard_match("* > jeans", "women > jeans > slim"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+
+    #[test]
+    fn paths_are_compared_without_case_or_spacing() {
+        assert_eq!(
+            normalize_path("men>Tops >  T-Shirts"),
+            "men > tops > t-shirts"
+        );
+        let map = map(&[("Men > Tops > T-Shirts", "Clothing > Men > Tees")]);
+        assert_eq!(
+            target(&map, " MEN >tops>t-shirts "),
+            Some(String::from("rule Clothing > Men > Tees"))
+        );
+    }
+
+    #[test]
+    fn exact_then_most_specific_wildcard_then_fallback() {
+        let rules = map(&[
+            ("*", "Uncategorized"),
+            ("Men > *", "Men"),
+            ("Men > Shoes > *", "Footwear > Men"),
+            ("Men > Shoes > Boots", "Boots"),
+        ]);
+        assert_eq!(
+            target(&rules, "Men > Shoes > Boots"),
+            Some(String::from("rule Boots"))
+        );
+        assert_eq!(
+            target(&rules, "Men > Shoes > Sneakers"),
+            Some(String::from("rule Footwear > Men"))
+        );
+        assert_eq!(target(&rules, "Men > Hats"), Some(String::from("rule Men")));
+        assert_eq!(
+            target(&rules, "Kids > Toys"),
+            Some(String::from("fallback Uncategorized"))
+        );
+
+        // the first of two equally specific rules wins, and no "*" means no hit
+        let tied = map(&[("Mens > *", "First"), ("* > Hats", "Second")]);
+        assert_eq!(
+            target(&tied, "Mens > Hats"),
+            Some(String::from("rule First"))
+        );
+        assert_eq!(target(&tied, "Kids > Toys"), None);
+    }
+
+    #[test]
+    fn apply_rewrites_categories_and_reports_the_unmatched() {
+        let object = |category: &str| {
+            let product: VendooProduct = serde_json::from_value(serde_json::json!({
+                "Sku": "A-1",
+                "Title": "Thing",
+                "Status": "Active",
+            }))
+            .unwrap();
+            let mut object = LocalObject::from_vendoo_object(&product);
+            object.categories = String::from(category);
+            object
+        };
+        let mut session = LocalSession {
+            n: 3,
+            local_vp: vec![
+                object("Men > Shoes > Boots, Men > Shoes > Sneakers"),
+                object("Kids > Toys"),
+                object("Kids > Toys, "),
+            ],
+            local_wp: Vec::new(),
+            current_idx: 0,
+        };
+
+        let report = map(&[("Men > Shoes > *", "Footwear > Men")]).apply(&mut session);
+        assert_eq!(session.local_vp[0].categories, "Footwear > Men");
+        assert_eq!(session.local_vp[1].categories, "Kids > Toys");
+        assert_eq!(report.mapped, 2);
+        assert_eq!(report.fallback, 0);
+        assert_eq!(
+            report.unmatched,
+            BTreeMap::from([(String::from("Kids > Toys"), 2)])
+        );
+
+        let report = map(&[("*", "Uncategorized")]).apply(&mut session);
+        assert_eq!(session.local_vp[1].categories, "Uncategorized");
+        assert_eq!(report.fallback, 3);
+        assert_eq!(report.unmatched.len(), 2);
+    }
+}
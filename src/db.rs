@@ -0,0 +1,588 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{params, Connection};
+
+use crate::{
+    local::{hash_title, source_id_for_images, unix_now, DelistPolicy, LocalObject, LocalSession},
+    matching::{normalize_sku, MatchReport},
+    obj_vd::VendooProduct,
+    obj_wc::{BatchOp, BatchReport, WooCommerceProduct},
+};
+
+// LOCAL_DB ending in .json is a LocalSession file (LocalSession::save), anything else is
+// the sqlite store below
+pub fn is_session_json(path: &str) -> bool {
+    path.to_lowercase().ends_with(".json")
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS vendoo_products (
+    vd_key      TEXT PRIMARY KEY,
+    sku         TEXT NOT NULL,
+    title       TEXT NOT NULL,
+    status      TEXT NOT NULL,
+    hash_hex    TEXT NOT NULL,
+    source_id   TEXT,
+    row_json    TEXT NOT NULL,
+    imported_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS wc_products (
+    wc_id      INTEGER PRIMARY KEY,
+    sku        TEXT NOT NULL,
+    name       TEXT NOT NULL,
+    status     TEXT NOT NULL,
+    hash_hex   TEXT NOT NULL,
+    source_id  TEXT,
+    row_json   TEXT NOT NULL,
+    fetched_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS product_map (
+    vd_key     TEXT PRIMARY KEY,
+    wc_id      INTEGER NOT NULL,
+    matched_by TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sync_history (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_at        INTEGER NOT NULL,
+    op            TEXT NOT NULL,
+    wc_id         INTEGER,
+    sku           TEXT NOT NULL,
+    name          TEXT NOT NULL,
+    ok            INTEGER NOT NULL,
+    error_code    TEXT,
+    error_message TEXT
+);
+CREATE INDEX IF NOT EXISTS wc_products_sku ON wc_products (sku);
+CREATE INDEX IF NOT EXISTS wc_products_hash ON wc_products (hash_hex);
+CREATE INDEX IF NOT EXISTS vendoo_products_status ON vendoo_products (status);
+";
+
+#[derive(Debug, Clone)]
+pub struct SyncHistoryRow {
+    pub run_at: u64,
+    pub op: String,
+    pub wc_id: Option<u64>,
+    pub sku: String,
+    pub name: String,
+    pub ok: bool,
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+}
+
+impl SyncHistoryRow {
+    pub fn debug(&self) -> String {
+        let outcome = match self.ok {
+            true => String::from("ok"),
+            false => format!(
+                "FAILED {}: {}",
+                self.error_code.clone().unwrap_or_default(),
+                self.error_message.clone().unwrap_or_default()
+            ),
+        };
+        format!(
+            "[{}] {} #{} {} (SKU: {}) {}",
+            self.run_at,
+            self.op,
+            self.wc_id
+                .map(|id| id.to_string())
+                .unwrap_or(String::from("-")),
+            self.name,
+            self.sku,
+            outcome
+        )
+    }
+}
+
+pub struct LocalDb {
+    conn: Connection,
+    pub path: String,
+}
+
+impl LocalDb {
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn,
+            path: path.to_owned(),
+        })
+    }
+
+    // replaces the vendoo table with the rows from the latest CSV. rows sharing a key (two
+    // listings with the same SKU, usually) would overwrite each other, so the first one is
+    // kept and the rest come back in the report.
+    pub fn import_vendoo(
+        &mut self,
+        products: &[VendooProduct],
+    ) -> Result<VendooImport, Box<dyn std::error::Error>> {
+        let now = unix_now() as i64;
+        let mut import = VendooImport::default();
+        let mut seen: HashMap<String, String> = HashMap::new();
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM vendoo_products", [])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO vendoo_products
+                 (vd_key, sku, title, status, hash_hex, source_id, row_json, imported_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for product in products {
+                let key = vd_key_for(product);
+                let title = product.title.clone().unwrap_or_default();
+                if let Some(kept) = seen.get(&key) {
+                    import.duplicates.push(DuplicateRow {
+                        key,
+                        kept: kept.clone(),
+                        dropped: title,
+                    });
+                    continue;
+                }
+                stmt.execute(params![
+                    key,
+                    product.sku.clone().unwrap_or_default(),
+                    title,
+                    product.status.clone().unwrap_or_default(),
+                    hash_title(&title),
+                    source_id_for_images(&product.image_urls()),
+                    serde_json::to_string(product)?,
+                    now
+                ])?;
+                seen.insert(key, title);
+                import.imported += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(import)
+    }
+
+    // replaces the WooCommerce snapshot with a fresh fetch
+    pub fn import_woocommerce(
+        &mut self,
+        products: &[WooCommerceProduct],
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let now = unix_now() as i64;
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM wc_products", [])?;
+        let mut n = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO wc_products
+                 (wc_id, sku, name, status, hash_hex, source_id, row_json, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for product in products {
+                let wc_id = match product.id {
+                    Some(wc_id) => wc_id as i64,
+                    None => continue,
+                };
+                let local = LocalObject::from_woocommerce_object(product);
+                stmt.execute(params![
+                    wc_id,
+                    local.sku,
+                    local.name,
+                    local.status,
+                    local.hash_hex,
+                    local.source_id,
+                    serde_json::to_string(product)?,
+                    now
+                ])?;
+                n += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    // remembers every pairing the matcher made so the next run can look it up
+    pub fn record_matches(
+        &mut self,
+        session: &LocalSession,
+        report: &MatchReport,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let now = unix_now() as i64;
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO product_map (vd_key, wc_id, matched_by, updated_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for product_match in &report.matches {
+                let vp_object = &session.local_vp[product_match.vp_idx];
+                let wc_id = match session.local_wp[product_match.wp_idx].wc_id {
+                    Some(wc_id) => wc_id as i64,
+                    None => continue,
+                };
+                stmt.execute(params![
+                    vd_key(vp_object),
+                    wc_id,
+                    format!("{:?}", product_match.key),
+                    now
+                ])?;
+                n += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    // writes every batch result to sync_history and maps newly created products
+    pub fn record_batch(&mut self, report: &BatchReport) -> Result<(), Box<dyn std::error::Error>> {
+        let now = unix_now() as i64;
+        let tx = self.conn.transaction()?;
+        {
+            let mut history = tx.prepare(
+                "INSERT INTO sync_history
+                 (run_at, op, wc_id, sku, name, ok, error_code, error_message)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            let mut map = tx.prepare(
+                "INSERT OR REPLACE INTO product_map (vd_key, wc_id, matched_by, updated_at)
+                 SELECT vd_key, ?1, 'Create', ?2 FROM vendoo_products
+                 WHERE (?3 <> '' AND upper(trim(sku)) = ?3) OR (?3 = '' AND title = ?4)",
+            )?;
+            for result in &report.results {
+                history.execute(params![
+                    now,
+                    format!("{:?}", result.op),
+                    result.id.map(|id| id as i64),
+                    result.sku,
+                    result.name,
+                    result.is_ok(),
+                    result.error.as_ref().map(|e| e.code.clone()),
+                    result.error.as_ref().map(|e| e.message.clone())
+                ])?;
+                if let (BatchOp::Create, true, Some(wc_id)) = (result.op, result.is_ok(), result.id)
+                {
+                    map.execute(params![
+                        wc_id as i64,
+                        now,
+                        normalize_sku(&result.sku).unwrap_or_default(),
+                        result.name
+                    ])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // active vendoo rows with nothing in the store: no mapping, or a mapping to a product
+    // that's gone from the latest fetch, and nothing else in the store that looks like them
+    pub fn need_posted(&self) -> Result<Vec<VendooProduct>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT v.row_json FROM vendoo_products v
+             LEFT JOIN product_map m ON m.vd_key = v.vd_key
+             LEFT JOIN wc_products mw ON mw.wc_id = m.wc_id
+             WHERE lower(v.status) = 'active' AND mw.wc_id IS NULL
+             AND NOT EXISTS (
+                 SELECT 1 FROM wc_products w
+                 WHERE (v.sku <> '' AND upper(w.sku) = upper(v.sku))
+                 OR (v.source_id IS NOT NULL AND w.source_id = v.source_id)
+                 OR (w.hash_hex = v.hash_hex AND (w.sku = '' OR upper(w.sku) = upper(v.sku)))
+             )
+             ORDER BY v.title",
+        )?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut products: Vec<VendooProduct> = Vec::new();
+        for row in rows {
+            products.push(serde_json::from_str(&row?)?);
+        }
+        Ok(products)
+    }
+
+    // narrows postable (usually LocalSession::compare_wc_vd) to what need_posted agrees on.
+    // only narrows: the db keeps one row per key, so it can't tell the matcher's held back
+    // duplicate SKUs apart and would post every one of them.
+    pub fn retain_need_posted(
+        &self,
+        postable: &mut Vec<LocalObject>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let keys: HashSet<String> = self.need_posted()?.iter().map(vd_key_for).collect();
+        postable.retain(|object| keys.contains(&vd_key(object)));
+        Ok(())
+    }
+
+    // mapped WooCommerce products whose vendoo row sold or got archived and that aren't
+    // already delisted the way policy would do it, as (wc_id, row)
+    pub fn need_delisting(
+        &self,
+        policy: DelistPolicy,
+    ) -> Result<Vec<(u64, VendooProduct)>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT m.wc_id, v.row_json, w.row_json FROM vendoo_products v
+             JOIN product_map m ON m.vd_key = v.vd_key
+             JOIN wc_products w ON w.wc_id = m.wc_id
+             WHERE lower(v.status) IN ('sold', 'archived', 'delisted', 'deleted', 'inactive')
+             AND w.status <> 'trash'
+             ORDER BY v.title",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut products: Vec<(u64, VendooProduct)> = Vec::new();
+        for row in rows {
+            let (wc_id, row_json, wc_json) = row?;
+            let wprod: WooCommerceProduct = serde_json::from_str(&wc_json)?;
+            if LocalObject::from_woocommerce_object(&wprod).is_delisted_as(policy.action) {
+                continue;
+            }
+            products.push((wc_id as u64, serde_json::from_str(&row_json)?));
+        }
+        Ok(products)
+    }
+
+    pub fn history(&self, limit: usize) -> Result<Vec<SyncHistoryRow>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT run_at, op, wc_id, sku, name, ok, error_code, error_message
+             FROM sync_history ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok(SyncHistoryRow {
+                run_at: row.get::<_, i64>(0)? as u64,
+                op: row.get(1)?,
+                wc_id: row.get::<_, Option<i64>>(2)?.map(|id| id as u64),
+                sku: row.get(3)?,
+                name: row.get(4)?,
+                ok: row.get(5)?,
+                error_code: row.get(6)?,
+                error_message: row.get(7)?,
+            })
+        })?;
+
+        let mut history: Vec<SyncHistoryRow> = Vec::new();
+        for row in rows {
+            history.push(row?);
+        }
+        Ok(history)
+    }
+
+    pub fn debug(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let count = |table: &str| -> Result<i64, rusqlite::Error> {
+            self.conn
+                .query_row(&format!("SELECT count(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+        };
+        Ok(format!(
+            "--- LOCAL DB ---
+PATH: {}
+VENDOO ROWS: {}
+WOOCOMMERCE PRODUCTS: {}
+MAPPED: {}
+SYNC HISTORY: {}",
+            self.path,
+            count("vendoo_products")?,
+            count("wc_products")?,
+            count("product_map")?,
+            count("sync_history")?
+        ))
+    }
+}
+
+// the key a vendoo row is stored and mapped under: SKU, else source id, else title hash
+pub fn vd_key(object: &LocalObject) -> String {
+    key_from(&object.sku, object.source_id.as_deref(), &object.hash_hex)
+}
+
+// the same key straight from the CSV row, without rendering templates or the rest of
+// LocalObject::from_vendoo_object
+pub fn vd_key_for(product: &VendooProduct) -> String {
+    let source_id = source_id_for_images(&product.image_urls());
+    key_from(
+        product.sku.as_deref().unwrap_or(""),
+        source_id.as_deref(),
+        &hash_title(product.title.as_deref().unwrap_or("")),
+    )
+}
+
+fn key_from(sku: &str, source_id: Option<&str>, hash_hex: &str) -> String {
+    if let Some(sku) = normalize_sku(sku) {
+        return format!("sku:{}", sku);
+    }
+    if let Some(source_id) = source_id {
+        return format!("src:{}", source_id);
+    }
+    format!("title:{}", hash_hex)
+}
+
+// what import_vendoo did with the CSV
+#[derive(Debug, Clone, Default)]
+pub struct VendooImport {
+    pub imported: usize,
+    pub duplicates: Vec<DuplicateRow>,
+}
+
+// a row that shared its key with an earlier one and was left out
+#[derive(Debug, Clone)]
+pub struct DuplicateRow {
+    pub key: String,
+    pub kept: String,    // title of the row that was imported
+    pub dropped: String, // and of the one that wasn't
+}
+
+impl VendooImport {
+    pub fn debug(&self) -> String {
+        let mut str = format!(
+            "[] imported {} vendoo rows, {} duplicates left out",
+            self.imported,
+            self.duplicates.len()
+        );
+        for duplicate in &self.duplicates {
+            str.push_str(&format!(
+                "\n[] DUPLICATE {}: kept {:?}, dropped {:?}",
+                duplicate.key, duplicate.kept, duplicate.dropped
+            ));
+        }
+        str
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::local::DelistAction;
+
+    fn vendoo(sku: &str, title: &str, status: &str) -> VendooProduct {
+        serde_json::from_value(serde_json::json!({
+            "Sku": sku,
+            "Title": title,
+            "Status": status,
+        }))
+        .unwrap()
+    }
+
+    fn woocommerce(wc_id: u64, sku: &str, status: &str, stock_status: &str) -> WooCommerceProduct {
+        serde_json::from_value(serde_json::json!({
+            "id": wc_id,
+            "name": format!("Product {}", wc_id),
+            "regular_price": "10.00",
+            "description": "",
+            "categories": [],
+            "images": [],
+            "stock_quantity": null,
+            "status": status,
+            "stock_status": stock_status,
+            "sku": sku,
+        }))
+        .unwrap()
+    }
+
+    fn map(db: &LocalDb, product: &VendooProduct, wc_id: u64) {
+        db.conn
+            .execute(
+                "INSERT INTO product_map (vd_key, wc_id, matched_by, updated_at)
+                 VALUES (?1, ?2, 'Sku', 0)",
+                params![vd_key_for(product), wc_id as i64],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn duplicate_keys_are_reported_not_overwritten() {
+        let mut db = LocalDb::open(":memory:").unwrap();
+        let import = db
+            .import_vendoo(&[
+                vendoo("ab-1", "First", "Active"),
+                vendoo("AB-1", "Second", "Active"),
+                vendoo("AB-2", "Third", "Active"),
+            ])
+            .unwrap();
+
+        assert_eq!(import.imported, 2);
+        assert_eq!(import.duplicates.len(), 1);
+        assert_eq!(import.duplicates[0].kept, "First");
+        assert_eq!(import.duplicates[0].dropped, "Second");
+        let titles: Vec<String> = db
+            .need_posted()
+            .unwrap()
+            .into_iter()
+            .filter_map(|product| product.title)
+            .collect();
+        assert_eq!(titles, vec!["First", "Third"]);
+    }
+
+    #[test]
+    fn duplicate_skus_stay_held_back() {
+        let mut db = LocalDb::open(":memory:").unwrap();
+        let rows = vec![
+            vendoo("ab-1", "First", "Active"),
+            vendoo("AB-1", "Second", "Active"),
+            vendoo("AB-2", "Third", "Active"),
+        ];
+        db.import_vendoo(&rows).unwrap();
+
+        let session = LocalSession {
+            n: rows.len() as i32,
+            local_vp: rows.iter().map(LocalObject::from_vendoo_object).collect(),
+            local_wp: Vec::new(),
+            current_idx: 0,
+        };
+        let (_, mut postable) = session.compare_wc_vd();
+        db.retain_need_posted(&mut postable).unwrap();
+
+        let names: Vec<&str> = postable.iter().map(|object| object.name.as_str()).collect();
+        assert_eq!(names, vec!["Third"]);
+    }
+
+    #[test]
+    fn mapping_to_a_missing_product_needs_posting() {
+        let mut db = LocalDb::open(":memory:").unwrap();
+        let kept = vendoo("AB-1", "Kept", "Active");
+        let gone = vendoo("AB-2", "Deleted from the store", "Active");
+        db.import_vendoo(&[kept.clone(), gone.clone()]).unwrap();
+        db.import_woocommerce(&[woocommerce(7, "AB-1", "publish", "instock")])
+            .unwrap();
+        map(&db, &kept, 7);
+        map(&db, &gone, 8);
+
+        let posted = db.need_posted().unwrap();
+        assert_eq!(posted.len(), 1);
+        assert_eq!(posted[0].sku.as_deref(), Some("AB-2"));
+    }
+
+    #[test]
+    fn delisting_skips_products_already_in_the_target_state() {
+        let mut db = LocalDb::open(":memory:").unwrap();
+        let sold: Vec<VendooProduct> = (1..=4)
+            .map(|i| vendoo(&format!("AB-{}", i), &format!("Sold {}", i), "Sold"))
+            .collect();
+        db.import_vendoo(&sold).unwrap();
+        db.import_woocommerce(&[
+            woocommerce(1, "AB-1", "publish", "instock"),
+            woocommerce(2, "AB-2", "publish", "outofstock"),
+            woocommerce(3, "AB-3", "draft", "instock"),
+            woocommerce(4, "AB-4", "trash", "instock"),
+        ])
+        .unwrap();
+        for (i, product) in sold.iter().enumerate() {
+            map(&db, product, i as u64 + 1);
+        }
+
+        let ids = |action: DelistAction| -> Vec<u64> {
+            let policy = DelistPolicy {
+                action,
+                include_missing: false,
+            };
+            let mut ids: Vec<u64> = db
+                .need_delisting(policy)
+                .unwrap()
+                .into_iter()
+                .map(|(wc_id, _)| wc_id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(DelistAction::OutOfStock), vec![1, 3]);
+        assert_eq!(ids(DelistAction::Draft), vec![1, 2]);
+        assert_eq!(ids(DelistAction::Trash), vec![1, 2, 3]);
+    }
+}
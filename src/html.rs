@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+// descriptions stay HTML wherever they're stored or sent. these are for the two places
+// that can't take it as is: comparing ours against the store's, and printing to a terminal.
+
+// wide enough that wrapping doesn't matter for comparisons
+pub const TEXT_WIDTH: usize = 100;
+
+// roughly what WordPress keeps in a post description. anything else is dropped, and
+// <script>/<style> go with their contents.
+fn allowlist() -> &'static ammonia::Builder<'static> {
+    static ALLOWLIST: OnceLock<ammonia::Builder<'static>> = OnceLock::new();
+    ALLOWLIST.get_or_init(|| {
+        let mut builder = ammonia::Builder::empty();
+        builder
+            .tags(HashSet::from([
+                "p",
+                "br",
+                "hr",
+                "div",
+                "span",
+                "strong",
+                "b",
+                "em",
+                "i",
+                "u",
+                "s",
+                "del",
+                "ins",
+                "small",
+                "sub",
+                "sup",
+                "h1",
+                "h2",
+                "h3",
+                "h4",
+                "h5",
+                "h6",
+                "ul",
+                "ol",
+                "li",
+                "blockquote",
+                "pre",
+                "code",
+                "a",
+                "img",
+                "table",
+                "thead",
+                "tbody",
+                "tr",
+                "th",
+                "td",
+            ]))
+            .tag_attributes(HashMap::from([
+                ("a", HashSet::from(["href", "title"])),
+                ("img", HashSet::from(["src", "alt", "width", "height"])),
+                ("td", HashSet::from(["colspan", "rowspan"])),
+                ("th", HashSet::from(["colspan", "rowspan"])),
+            ]))
+            .url_schemes(HashSet::from(["http", "https", "mailto"]))
+            .clean_content_tags(HashSet::from(["script", "style"]))
+            .link_rel(None);
+        builder
+    })
+}
+
+// the description with only allowlisted tags and attributes left
+pub fn sanitize(html: &str) -> String {
+    allowlist().clean(html).to_string()
+}
+
+// sanitized, then as plain text: blocks on their own lines, lists as "* item", links as
+// footnotes, entities decoded
+pub fn to_text(html: &str) -> String {
+    let clean = sanitize(html);
+    html2text::from_read(clean.as_bytes(), TEXT_WIDTH)
+        .map(|text| text.trim().to_owned())
+        .unwrap_or(clean)
+}
+
+// what two descriptions are compared by. WordPress reflows whitespace, re-encodes
+// entities and drops markup it doesn't allow when it saves one, none of that is a change.
+pub fn comparable(html: &str) -> String {
+    to_text(html)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_keeps_formatting_and_drops_the_rest() {
+        let html = "<p class=\"x\" onclick=\"evil()\">Hi <strong>there</strong></p>\
+                    <script>alert(1)</script><iframe src=\"https://x\"></iframe>\
+                    <a href=\"javascript:alert(1)\">bad</a><a href=\"https://ok.example\">ok</a>";
+        assert_eq!(
+            sanitize(html),
+            "<p>Hi <strong>there</strong></p><a>bad</a><a href=\"https://ok.example\">ok</a>"
+        );
+    }
+
+    #[test]
+    fn text_keeps_structure() {
+        let text = to_text(
+            "<h2>Levi&#8217;s 501</h2><p>Size 32 &amp; 34</p><ul><li>Blue</li><li>Cotton</li></ul>",
+        );
+        assert!(text.contains("Levi’s 501"), "{}", text);
+        assert!(text.contains("Size 32 & 34"), "{}", text);
+        assert!(text.contains("* Blue\n* Cotton"), "{}", text);
+    }
+
+    #[test]
+    fn comparable_ignores_what_wordpress_changes() {
+        let sent = "<p>Tom & Jerry</p>\n\n<p>Size <b>M</b></p><script>track()</script>";
+        let stored = "<p>Tom &amp; Jerry</p>\r\n<p>Size   <b>M</b></p>";
+        assert_eq!(comparable(sent), comparable(stored));
+        assert_ne!(
+            comparable(sent),
+            comparable("<p>Tom & Jerry</p><p>Size L</p>")
+        );
+        // plain vendoo text with angle brackets survives
+        assert_eq!(comparable("a < b > c"), "a < b > c");
+    }
+}
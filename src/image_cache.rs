@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::obj_vd::{ExternalImage, ObjVendoo};
+use crate::obj_wc::ObjWooCommerce;
+use crate::retry::{send_with_retry, Idempotency, RetryPolicy};
+
+pub const DEFAULT_IMAGE_CACHE: &str = "image_cache";
+const INDEX_FILE: &str = "index.json";
+pub(crate) const MEDIA_FILE: &str = "media.json";
+
+// what a vendoo URL turned into on disk
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CachedImage {
+    pub sha256: String,
+    pub file: String, // relative to the cache dir
+}
+
+// content-addressed image store: every file is named after the sha256 of its bytes, and
+// index.json remembers which URL produced which file so a re-sync never re-downloads.
+// media.json remembers which sha256 is already in the WordPress media library, see media.rs.
+#[derive(Debug, Clone)]
+pub struct ImageCache {
+    pub dir: PathBuf,
+    pub index: BTreeMap<String, CachedImage>,
+    pub media: BTreeMap<String, u64>, // sha256 -> WordPress media id
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImageCacheReport {
+    pub downloaded: usize,
+    pub cached: usize,
+    pub failed: Vec<(String, String)>, // url, error
+}
+
+impl ImageCacheReport {
+    pub fn debug(&self) -> String {
+        let mut str = format!(
+            "--- IMAGE CACHE ---\n{} downloaded, {} already cached, {} failed\n",
+            self.downloaded,
+            self.cached,
+            self.failed.len()
+        );
+        for (src, e) in &self.failed {
+            str.push_str(&format!("    FAILED: {} ({})\n", src, e));
+        }
+        str
+    }
+}
+
+impl ImageCache {
+    pub fn open(dir: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let index = read_json_or_default(&dir.join(INDEX_FILE))?;
+        let media = read_json_or_default(&dir.join(MEDIA_FILE))?;
+
+        Ok(Self { dir, index, media })
+    }
+
+    // IMAGE_CACHE in .env, ./image_cache if it isn't set
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = std::env::var("IMAGE_CACHE").unwrap_or(String::from(DEFAULT_IMAGE_CACHE));
+        Self::open(&dir)
+    }
+
+    pub fn path_of(&self, cached: &CachedImage) -> PathBuf {
+        self.dir.join(&cached.file)
+    }
+
+    // the cached copy of a URL, but only if the file is still actually there
+    pub fn lookup(&self, src: &str) -> Option<&CachedImage> {
+        self.index
+            .get(src)
+            .filter(|cached| self.path_of(cached).exists())
+    }
+
+    pub fn save_index(&self) -> Result<(), Box<dyn std::error::Error>> {
+        write_json(&self.dir, INDEX_FILE, &self.index)
+    }
+
+    pub fn save_media(&self) -> Result<(), Box<dyn std::error::Error>> {
+        write_json(&self.dir, MEDIA_FILE, &self.media)
+    }
+
+    // downloads src unless it's already cached. transient failures are retried like any
+    // store request, see retry.rs
+    pub async fn fetch(
+        &mut self,
+        client: &Client,
+        retry: &RetryPolicy,
+        src: &str,
+    ) -> Result<(CachedImage, bool), Box<dyn std::error::Error>> {
+        if let Some(cached) = self.lookup(src) {
+            return Ok((cached.clone(), false));
+        }
+
+        let (bytes, content_type) = download(client, retry, src).await?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(&bytes);
+        let sha256 = hex::encode(hasher.finalize());
+        let file = format!("{}.{}", sha256, image_extension(src, &content_type));
+        let cached = CachedImage { sha256, file };
+
+        // two listings sharing a photo land on the same file, only write it once
+        let path = self.path_of(&cached);
+        if !path.exists() {
+            let tmp = path.with_extension("part");
+            std::fs::write(&tmp, &bytes)?;
+            std::fs::rename(&tmp, &path)?;
+        }
+
+        self.index.insert(src.to_owned(), cached.clone());
+        self.save_index()?;
+        Ok((cached, true))
+    }
+}
+
+impl ObjVendoo {
+    // pulls every product image into the cache and fills external_img with where each one
+    // ended up. failures are reported, not fatal, the URL just stays uncached for next time.
+    // downloads go through the store's client and retry policy.
+    pub async fn cache_images(
+        &mut self,
+        cache: &mut ImageCache,
+        store: &ObjWooCommerce,
+    ) -> Result<ImageCacheReport, Box<dyn std::error::Error>> {
+        let mut report = ImageCacheReport::default();
+        let mut external_img: Vec<ExternalImage> = Vec::new();
+
+        for product in self.products.clone().unwrap_or_default() {
+            let owner = product
+                .sku
+                .clone()
+                .filter(|sku| !sku.trim().is_empty())
+                .or(product.title.clone())
+                .unwrap_or_default();
+
+            for (position, src) in product.image_urls().into_iter().enumerate() {
+                let cached = match cache.fetch(&store.client, &store.retry, &src).await {
+                    Ok((cached, downloaded)) => {
+                        if downloaded {
+                            report.downloaded += 1;
+                        } else {
+                            report.cached += 1;
+                        }
+                        Some(cached)
+                    }
+                    Err(e) => {
+                        report.failed.push((src.clone(), e.to_string()));
+                        None
+                    }
+                };
+
+                external_img.push(ExternalImage {
+                    src,
+                    product: owner.clone(),
+                    position: position as u32,
+                    sha256: cached.as_ref().map(|cached| cached.sha256.clone()),
+                    path: cached
+                        .as_ref()
+                        .map(|cached| cache.path_of(cached).to_string_lossy().to_string()),
+                });
+            }
+        }
+
+        self.external_img = Some(external_img);
+        Ok(report)
+    }
+}
+
+fn read_json_or_default<T: serde::de::DeserializeOwned + Default>(
+    path: &Path,
+) -> Result<T, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let str = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&str)?)
+}
+
+fn write_json<T: Serialize>(
+    dir: &Path,
+    name: &str,
+    value: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string_pretty(value)?;
+    let tmp = dir.join(format!("{}.tmp", name));
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(tmp, dir.join(name))?;
+    Ok(())
+}
+
+async fn download(
+    client: &Client,
+    retry: &RetryPolicy,
+    src: &str,
+) -> Result<(Vec<u8>, Option<String>), Box<dyn std::error::Error>> {
+    let response = send_with_retry(retry, Idempotency::Idempotent, || client.get(src)).await?;
+    if !response.status().is_success() {
+        let error_msg = format!("HTTP {}", response.status());
+        return Err(error_msg.into());
+    }
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned());
+    let bytes = response.bytes().await?;
+    if bytes.is_empty() {
+        return Err("empty response body".into());
+    }
+    Ok((bytes.to_vec(), content_type))
+}
+
+// the URL's extension if it looks like an image, otherwise whatever the server said it sent
+fn image_extension(src: &str, content_type: &Option<String>) -> String {
+    let from_url = reqwest::Url::parse(src).ok().and_then(|url| {
+        Path::new(url.path())
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+    });
+    if let Some(ext) = from_url {
+        if ["jpg", "jpeg", "png", "gif", "webp"].contains(&ext.as_str()) {
+            return ext;
+        }
+    }
+
+    match content_type.as_deref() {
+        Some("image/png") => String::from("png"),
+        Some("image/gif") => String::from("gif"),
+        Some("image/webp") => String::from("webp"),
+        _ => String::from("jpg"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::retry::random_u64;
+
+    const PRODUCT_PNG: &[u8] = include_bytes!("../tests/fixtures/product.png");
+
+    #[tokio::test]
+    async fn downloads_retry_through_the_store_policy() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/img/a.png"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/img/a.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(PRODUCT_PNG))
+            .mount(&server)
+            .await;
+
+        let mut store =
+            ObjWooCommerce::new_with_auth(server.uri(), String::from("ck"), String::from("cs"));
+        store.retry = RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        };
+        let mut vendoo = ObjVendoo {
+            csv_path: None,
+            products: Some(vec![serde_json::from_value(serde_json::json!({
+                "Sku": "A-1",
+                "Images": format!("{}/img/a.png", server.uri()),
+            }))
+            .unwrap()]),
+            external_img: None,
+        };
+        let dir = std::env::temp_dir().join(format!("hcrelay-cache-{}", random_u64()));
+        let mut cache = ImageCache::open(&dir.to_string_lossy()).unwrap();
+
+        let report = vendoo.cache_images(&mut cache, &store).await.unwrap();
+        assert_eq!((report.downloaded, report.failed.len()), (1, 0));
+        let images = vendoo.external_img.clone().unwrap();
+        assert!(Path::new(images[0].path.as_ref().unwrap()).exists());
+
+        // the second run comes out of the index without asking again
+        let report = vendoo.cache_images(&mut cache, &store).await.unwrap();
+        assert_eq!((report.downloaded, report.cached), (0, 1));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
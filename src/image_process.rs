@@ -0,0 +1,361 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbaImage};
+use sha2::{Digest, Sha256};
+
+use crate::image_cache::{CachedImage, ImageCache};
+
+pub const DEFAULT_MAX_EDGE: u32 = 2048;
+pub const DEFAULT_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub fn from_str(str: &str) -> Option<Self> {
+        match str.trim().to_lowercase().replace(['-', ' '], "_").as_str() {
+            "top_left" => Some(Self::TopLeft),
+            "top_right" => Some(Self::TopRight),
+            "bottom_left" => Some(Self::BottomLeft),
+            "bottom_right" => Some(Self::BottomRight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    pub path: PathBuf, // a PNG, transparency is kept
+    pub corner: Corner,
+    pub scale: f32,   // watermark width as a fraction of the photo's width
+    pub opacity: f32, // 0.0 - 1.0, multiplied into the watermark's own alpha
+}
+
+// what happens to a cached photo before it goes up to the media library. decoding and
+// re-encoding never carries EXIF (or any other metadata) over, so anything but the no-op
+// config strips it; strip_metadata forces that re-encode even when nothing else changes.
+#[derive(Debug, Clone)]
+pub struct ImageProcessing {
+    pub max_edge: Option<u32>,
+    pub quality: u8, // JPEG only, PNGs stay lossless
+    pub strip_metadata: bool,
+    pub watermark: Option<Watermark>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub bytes: Vec<u8>,
+    pub extension: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ImageProcessing {
+    fn default() -> Self {
+        Self {
+            max_edge: Some(DEFAULT_MAX_EDGE),
+            quality: DEFAULT_QUALITY,
+            strip_metadata: true,
+            watermark: None,
+        }
+    }
+}
+
+impl ImageProcessing {
+    // uploads the cached bytes untouched
+    pub fn disabled() -> Self {
+        Self {
+            max_edge: None,
+            quality: DEFAULT_QUALITY,
+            strip_metadata: false,
+            watermark: None,
+        }
+    }
+
+    // IMAGE_MAX_EDGE (0 = don't resize), IMAGE_QUALITY, IMAGE_STRIP_METADATA, IMAGE_WATERMARK
+    // (a PNG path), IMAGE_WATERMARK_CORNER, IMAGE_WATERMARK_SCALE, IMAGE_WATERMARK_OPACITY.
+    // IMAGE_PROCESSING=off skips the whole stage.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        if var("IMAGE_PROCESSING").is_some_and(|v| v.trim().eq_ignore_ascii_case("off")) {
+            return Self::disabled();
+        }
+
+        let mut processing = Self::default();
+        if let Some(max_edge) = var("IMAGE_MAX_EDGE").and_then(|v| v.trim().parse::<u32>().ok()) {
+            processing.max_edge = if max_edge == 0 { None } else { Some(max_edge) };
+        }
+        if let Some(quality) = var("IMAGE_QUALITY").and_then(|v| v.trim().parse::<u8>().ok()) {
+            processing.quality = quality.clamp(1, 100);
+        }
+        if let Some(strip) = var("IMAGE_STRIP_METADATA") {
+            processing.strip_metadata = !matches!(
+                strip.trim().to_lowercase().as_str(),
+                "0" | "false" | "no" | "off"
+            );
+        }
+        if let Some(path) = var("IMAGE_WATERMARK") {
+            processing.watermark = Some(Watermark {
+                path: PathBuf::from(path),
+                corner: var("IMAGE_WATERMARK_CORNER")
+                    .and_then(|v| Corner::from_str(&v))
+                    .unwrap_or(Corner::BottomRight),
+                scale: var("IMAGE_WATERMARK_SCALE")
+                    .and_then(|v| v.trim().parse::<f32>().ok())
+                    .unwrap_or(0.2)
+                    .clamp(0.01, 1.0),
+                opacity: var("IMAGE_WATERMARK_OPACITY")
+                    .and_then(|v| v.trim().parse::<f32>().ok())
+                    .unwrap_or(0.6)
+                    .clamp(0.0, 1.0),
+            });
+        }
+        processing
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.max_edge.is_none() && !self.strip_metadata && self.watermark.is_none()
+    }
+
+    // short hash of the settings. processed files and media uploads are keyed on it, so
+    // changing the settings produces (and uploads) new versions instead of reusing old ones.
+    pub fn fingerprint(&self) -> String {
+        let watermark = match &self.watermark {
+            Some(watermark) => format!(
+                "{}:{:?}:{}:{}",
+                watermark.path.display(),
+                watermark.corner,
+                watermark.scale,
+                watermark.opacity
+            ),
+            None => String::from("none"),
+        };
+        let settings = format!(
+            "{:?}|{}|{}|{}",
+            self.max_edge, self.quality, self.strip_metadata, watermark
+        );
+        let mut hasher = Sha256::new();
+        hasher.update(settings);
+        hex::encode(hasher.finalize())[..12].to_owned()
+    }
+
+    pub fn process(&self, bytes: &[u8]) -> Result<ProcessedImage, Box<dyn std::error::Error>> {
+        let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+        let format = reader.format();
+        let mut decoder = reader.into_decoder()?;
+        // phones store the photo sideways and set a flag, bake it in before the flag is gone
+        let orientation = decoder.orientation()?;
+        let mut img = DynamicImage::from_decoder(decoder)?;
+        img.apply_orientation(orientation);
+
+        if let Some(max_edge) = self.max_edge {
+            if img.width() > max_edge || img.height() > max_edge {
+                img = img.resize(max_edge, max_edge, FilterType::Lanczos3);
+            }
+        }
+
+        if let Some(watermark) = &self.watermark {
+            img = apply_watermark(img, watermark)?;
+        }
+
+        let (width, height) = (img.width(), img.height());
+        let mut out: Vec<u8> = Vec::new();
+        let extension = if format == Some(ImageFormat::Png) {
+            img.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?;
+            "png"
+        } else {
+            let rgb = img.to_rgb8();
+            JpegEncoder::new_with_quality(&mut out, self.quality).encode_image(&rgb)?;
+            "jpg"
+        };
+
+        Ok(ProcessedImage {
+            bytes: out,
+            extension,
+            width,
+            height,
+        })
+    }
+
+    pub fn debug(&self) -> String {
+        let max_edge = match self.max_edge {
+            Some(max_edge) => format!("{}px", max_edge),
+            None => String::from("original"),
+        };
+        let watermark = match &self.watermark {
+            Some(watermark) => format!(
+                "{} ({:?}, {}%)",
+                watermark.path.display(),
+                watermark.corner,
+                (watermark.opacity * 100.0).round()
+            ),
+            None => String::from("none"),
+        };
+        format!(
+            "--- IMAGE PROCESSING ---\nMAX EDGE: {}\nQUALITY: {}\nSTRIP METADATA: {}\nWATERMARK: {}\n",
+            max_edge, self.quality, self.strip_metadata, watermark
+        )
+    }
+}
+
+impl ImageCache {
+    // the processed version of a cached image, made on first use and kept next to the
+    // originals under processed/. a no-op config just hands back the original.
+    pub fn processed(
+        &self,
+        cached: &CachedImage,
+        processing: &ImageProcessing,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let original = self.path_of(cached);
+        if processing.is_noop() {
+            return Ok(original);
+        }
+
+        let dir = self.dir.join("processed");
+        let stem = format!("{}-{}", cached.sha256, processing.fingerprint());
+        for extension in ["jpg", "png"] {
+            let path = dir.join(format!("{}.{}", stem, extension));
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+
+        let processed = processing.process(&std::fs::read(&original)?)?;
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.{}", stem, processed.extension));
+        let tmp = path.with_extension("part");
+        std::fs::write(&tmp, &processed.bytes)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+}
+
+fn apply_watermark(
+    img: DynamicImage,
+    watermark: &Watermark,
+) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    let mark = image::open(Path::new(&watermark.path))?;
+    let width = ((img.width() as f32 * watermark.scale).round() as u32).max(1);
+    let height =
+        ((mark.height() as f32 * width as f32 / mark.width() as f32).round() as u32).max(1);
+    let mut mark: RgbaImage = mark
+        .resize_exact(width, height, FilterType::Lanczos3)
+        .to_rgba8();
+    for pixel in mark.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * watermark.opacity).round() as u8;
+    }
+
+    let margin = (img.width().min(img.height()) / 50) as i64;
+    let (img_w, img_h) = (img.width() as i64, img.height() as i64);
+    let (mark_w, mark_h) = (mark.width() as i64, mark.height() as i64);
+    let (x, y) = match watermark.corner {
+        Corner::TopLeft => (margin, margin),
+        Corner::TopRight => (img_w - mark_w - margin, margin),
+        Corner::BottomLeft => (margin, img_h - mark_h - margin),
+        Corner::BottomRight => (img_w - mark_w - margin, img_h - mark_h - margin),
+    };
+
+    let mut base = img.to_rgba8();
+    imageops::overlay(&mut base, &mark, x, y);
+    Ok(DynamicImage::ImageRgba8(base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHONE_JPEG: &[u8] = include_bytes!("../tests/fixtures/phone_exif.jpg");
+    const PRODUCT_PNG: &[u8] = include_bytes!("../tests/fixtures/product.png");
+    const WATERMARK_PNG: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/watermark.png");
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn jpeg_is_rotated_resized_and_stripped() {
+        // 64x48 stored, EXIF orientation 6 and a GPS block, red square in the stored top-left
+        assert!(contains(PHONE_JPEG, b"Exif\0\0"));
+        let processing = ImageProcessing {
+            max_edge: Some(32),
+            ..ImageProcessing::default()
+        };
+
+        let processed = processing.process(PHONE_JPEG).unwrap();
+        assert_eq!(processed.extension, "jpg");
+        assert_eq!((processed.width, processed.height), (24, 32));
+        assert!(!contains(&processed.bytes, b"Exif"));
+
+        // rotating 90 clockwise moves the red square to the top-right
+        let out = image::load_from_memory(&processed.bytes).unwrap().to_rgb8();
+        assert_eq!(out.dimensions(), (24, 32));
+        let top_right = out.get_pixel(21, 2);
+        let top_left = out.get_pixel(2, 2);
+        assert!(top_right[0] > 150 && top_right[2] < 100, "{:?}", top_right);
+        assert!(top_left[2] > 150, "{:?}", top_left);
+    }
+
+    #[test]
+    fn png_stays_png_and_keeps_alpha() {
+        let processing = ImageProcessing {
+            max_edge: Some(20),
+            ..ImageProcessing::default()
+        };
+
+        let processed = processing.process(PRODUCT_PNG).unwrap();
+        assert_eq!(processed.extension, "png");
+        assert_eq!((processed.width, processed.height), (20, 15));
+
+        let out = image::load_from_memory(&processed.bytes)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(out.get_pixel(2, 7)[3], 255);
+        assert!((out.get_pixel(17, 7)[3] as i32 - 128).abs() <= 2);
+    }
+
+    #[test]
+    fn watermark_lands_in_the_chosen_corner() {
+        let processing = ImageProcessing {
+            max_edge: None,
+            strip_metadata: true,
+            watermark: Some(Watermark {
+                path: PathBuf::from(WATERMARK_PNG),
+                corner: Corner::BottomRight,
+                scale: 0.25,
+                opacity: 1.0,
+            }),
+            ..ImageProcessing::default()
+        };
+
+        let processed = processing.process(PRODUCT_PNG).unwrap();
+        let out = image::load_from_memory(&processed.bytes)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(out.dimensions(), (40, 30));
+        // a 10x10 white square, pushed in from the bottom-right by a 0px margin
+        assert_eq!(out.get_pixel(37, 27).0, [255, 255, 255, 255]);
+        assert_eq!(out.get_pixel(2, 2).0, [30, 160, 60, 255]);
+    }
+
+    #[test]
+    fn noop_config_is_detected_and_changes_the_fingerprint() {
+        assert!(ImageProcessing::disabled().is_noop());
+        assert!(!ImageProcessing::default().is_noop());
+        assert_ne!(
+            ImageProcessing::disabled().fingerprint(),
+            ImageProcessing::default().fingerprint()
+        );
+    }
+}
@@ -1,28 +1,51 @@
-use std::collections::HashSet;
-use std::io::Read;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 
 use hex::encode;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 use crate::{
+    attributes::{BRAND_ATTRIBUTE, COLOR_ATTRIBUTE, CONDITION_ATTRIBUTE},
+    html,
+    matching::{match_products, MatchReport},
+    money::{self, Money},
     obj_vd::{ExternalImage, ObjVendoo, VendooProduct},
-    obj_wc::{self, Category, Image, ObjWooCommerce, WooCommerceProduct},
+    obj_wc::{
+        self, normalize_term, BatchReport, Category, Image, MetaData, ObjWooCommerce,
+        ProductAttribute, Tag, WooCommerceProduct,
+    },
+    pricing::PriceTrace,
+    status_map::status_map,
+    tags::{is_public_tag, split_tags},
+    template::description_templates,
 };
 
-#[derive(Debug, Deserialize, Clone)]
+// bump when LocalSession/LocalObject change in a way serde defaults can't paper over
+pub const SESSION_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum Sig {
     WC,
     VD,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum JsonBuffer {
     LocalBuffer,
     RemoteBuffer,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// what save writes, the session plus enough to know who wrote it and when
+#[derive(Debug, Deserialize, Serialize)]
+struct SessionFile {
+    schema_version: u32,
+    saved_at: u64, // unix seconds
+    #[serde(flatten)]
+    session: LocalSession,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LocalSession {
     pub n: i32,
     pub local_vp: Vec<LocalObject>, // will have differing signatures
@@ -60,37 +83,106 @@ impl LocalSession {
     }
 
     pub fn from_local_json(filepath: &str) -> Self {
-        let mut n: i32 = 0;
-        let mut local_vp: Vec<LocalObject> = Vec::new();
-        let mut local_wp: Vec<LocalObject> = Vec::new();
-        let current_idx: i32 = 0;
+        match Self::load(filepath) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("[] couldn't load local session from {}: {}", filepath, e);
+                Self::from_local_products(Vec::new())
+            }
+        }
+    }
 
-        let mut f = std::fs::File::open(filepath).unwrap(); // TODO fix this
+    // reads a file written by save. a bare JSON array of LocalObjects (what LOCAL_DB held
+    // before the file was versioned) still loads as schema 0.
+    pub fn load(filepath: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut f = std::fs::File::open(filepath)?;
         let mut json = String::new();
-        f.read_to_string(&mut json).unwrap();
+        f.read_to_string(&mut json)?;
 
-        let local_objects: Vec<LocalObject> = serde_json::from_str(&json).unwrap_or(Vec::new());
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+        if value.is_array() {
+            let local_objects: Vec<LocalObject> = serde_json::from_value(value)?;
+            return Ok(Self::from_local_products(local_objects));
+        }
 
-        for local_object in local_objects {
-            n += 1;
+        let file: SessionFile = serde_json::from_value(value)?;
+        if file.schema_version > SESSION_SCHEMA_VERSION {
+            let error_msg = format!(
+                "{} was written with schema version {}, this hcrelay only knows up to {}",
+                filepath, file.schema_version, SESSION_SCHEMA_VERSION
+            );
+            return Err(error_msg.into());
+        }
 
-            match local_object.sig {
-                Sig::WC => {
-                    // wc product push 2 local_wp
-                    local_wp.push(local_object);
+        Ok(file.session)
+    }
+
+    // writes the session next to filepath first and renames it over, so a crash mid-write
+    // never leaves a half written file behind
+    pub fn save(&self, filepath: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let file = SessionFile {
+            schema_version: SESSION_SCHEMA_VERSION,
+            saved_at: unix_now(),
+            session: self.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)?;
+
+        let tmp_path = format!("{}.tmp", filepath);
+        {
+            let mut f = std::fs::File::create(&tmp_path)?;
+            f.write_all(json.as_bytes())?;
+            f.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, filepath)?;
+
+        Ok(())
+    }
+
+    // stamps last_synced on every object the batch went through without an error
+    pub fn mark_synced(&mut self, report: &BatchReport) {
+        let now = unix_now();
+
+        for result in report.results.iter().filter(|result| result.is_ok()) {
+            for wp_object in self.local_wp.iter_mut() {
+                if result.id.is_some() && wp_object.wc_id == result.id {
+                    wp_object.last_synced = Some(now);
                 }
-                Sig::VD => {
-                    // vd product push 2 local_vd
-                    local_vp.push(local_object);
+            }
+            for vp_object in self.local_vp.iter_mut() {
+                let same = if !result.sku.is_empty() {
+                    vp_object.sku == result.sku
+                } else {
+                    vp_object.name == result.name
+                };
+                if same {
+                    vp_object.last_synced = Some(now);
                 }
             }
         }
+    }
 
-        Self {
-            n,
-            local_vp,
-            local_wp,
-            current_idx,
+    // copies last_synced over from a session loaded off disk, so rebuilding from a fresh
+    // fetch doesn't forget when each product was last pushed
+    pub fn carry_sync_times(&mut self, previous: &LocalSession) {
+        let key = |object: &LocalObject| -> String {
+            match object.wc_id {
+                Some(wc_id) => format!("wc:{}", wc_id),
+                None if !object.sku.is_empty() => format!("sku:{}", object.sku),
+                None => format!("title:{}", object.hash_hex),
+            }
+        };
+
+        let mut times: HashMap<String, u64> = HashMap::new();
+        for object in previous.local_vp.iter().chain(previous.local_wp.iter()) {
+            if let Some(last_synced) = object.last_synced {
+                times.insert(key(object), last_synced);
+            }
+        }
+
+        for object in self.local_vp.iter_mut().chain(self.local_wp.iter_mut()) {
+            if object.last_synced.is_none() {
+                object.last_synced = times.get(&key(object)).copied();
+            }
         }
     }
 
@@ -119,100 +211,378 @@ impl LocalSession {
             current_idx,
         }
     }
+    // pairs local_vp with local_wp, see matching::match_products
+    pub fn match_wc_vd(&self) -> MatchReport {
+        match_products(&self.local_vp, &self.local_wp)
+    }
+
     pub fn compare_wc_vd(&self) -> (i32, Vec<LocalObject>) {
         let mut matched: i32 = 0;
         let mut need_posted: Vec<LocalObject> = Vec::new();
 
-        let wp_hashes: HashSet<_> = self
-            .local_wp
-            .iter()
-            .map(|wp_object| &wp_object.hash_hex)
-            .collect();
+        let report = self.match_wc_vd();
 
-        for vp_object in self.local_vp.clone() {
+        for product_match in &report.matches {
+            let vp_object = &self.local_vp[product_match.vp_idx];
+            let wp_object = &self.local_wp[product_match.wp_idx];
             if vp_object.status == "Active" {
-                if wp_hashes.contains(&vp_object.hash_hex) {
-                    matched += 1;
-                    println!(
-                        "wc: {} matches vd: {}!",
-                        vp_object.hash_hex, vp_object.hash_hex
-                    );
-                } else {
-                    need_posted.push(vp_object);
-                }
+                matched += 1;
+                println!(
+                    "wc: {} matches vd: {} by {:?}!",
+                    wp_object.hash_hex, vp_object.hash_hex, product_match.key
+                );
+            }
+        }
+
+        for vp_idx in &report.unmatched_vp {
+            let vp_object = &self.local_vp[*vp_idx];
+            if vp_object.status == "Active" {
+                need_posted.push(vp_object.clone());
             }
         }
 
         (matched, need_posted)
     }
+
+    // finds active vendoo products that already exist in WooCommerce but whose price, stock,
+    // description, images or status have drifted. only the changed fields end up in the patch.
+    pub fn compare_wc_vd_updates(&self) -> Vec<ProductUpdate> {
+        let mut updates: Vec<ProductUpdate> = Vec::new();
+
+        for product_match in self.match_wc_vd().matches {
+            let vp_object = &self.local_vp[product_match.vp_idx];
+            let wp_object = &self.local_wp[product_match.wp_idx];
+            if vp_object.status != "Active" {
+                continue;
+            }
+            let wc_id = match wp_object.wc_id {
+                Some(wc_id) => wc_id,
+                None => continue, // can't update what we can't address
+            };
+            if let Some(update) = vp_object.diff_against(wp_object, wc_id) {
+                updates.push(update);
+            }
+        }
+
+        updates
+    }
+
+    // finds WooCommerce products whose vendoo counterpart sold, got archived, or (if the
+    // policy says so) vanished from the CSV. products already in the target state are skipped.
+    pub fn compare_wc_vd_delisted(&self, policy: DelistPolicy) -> Vec<Delisting> {
+        let mut delistings: Vec<Delisting> = Vec::new();
+        let report = self.match_wc_vd();
+
+        let mut candidates: Vec<(&LocalObject, DelistReason)> = Vec::new();
+        for product_match in &report.matches {
+            let vp_object = &self.local_vp[product_match.vp_idx];
+            let wp_object = &self.local_wp[product_match.wp_idx];
+            if let Some(reason) = vp_object.delist_reason() {
+                candidates.push((wp_object, reason));
+            }
+        }
+        if policy.include_missing {
+            for wp_idx in &report.unmatched_wp {
+                candidates.push((&self.local_wp[*wp_idx], DelistReason::Missing));
+            }
+        }
+
+        for (wp_object, reason) in candidates {
+            let wc_id = match wp_object.wc_id {
+                Some(wc_id) => wc_id,
+                None => continue,
+            };
+            if wp_object.is_delisted_as(policy.action) {
+                continue;
+            }
+
+            delistings.push(Delisting {
+                wc_id,
+                name: wp_object.name.clone(),
+                sku: wp_object.sku.clone(),
+                reason,
+                action: policy.action,
+            });
+        }
+
+        delistings
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LocalObject {
     // VendooProduct and WooCommerceProduct will both turn into this.
     pub sig: Sig,
-    pub hash_hex: String,
+    pub hash_hex: String, // sha256 of the normalized title
+    #[serde(default)]
+    pub wc_id: Option<u64>, // WooCommerce product id, only ever set on Sig::WC objects
+    #[serde(default)]
+    pub source_id: Option<String>, // survives a vendoo rename, see source_id_for_images
     pub name: String,
-    pub regular_price: String,
-    pub description: String,
+    #[serde(default, with = "money::empty_as_none")]
+    pub regular_price: Option<Money>, // None when vendoo has no price
+    pub description: String, // rendered through DESCRIPTION_TEMPLATE on the vendoo side
+    #[serde(default)]
+    pub short_description: String,
     pub categories: String,
-    pub images: Vec<String>,
+    pub images: Vec<String>, // in order, the first one is the featured image
     pub stock_quantity: Option<u32>,
+    #[serde(default)]
+    pub stock_status: Option<String>, // WooCommerce only
     pub status: String,
     pub sku: String,
+    #[serde(default)]
+    pub sold_date: Option<String>, // vendoo only, along with the two below
+    #[serde(default)]
+    pub sold_platform: Option<String>,
+    #[serde(default)]
+    pub quantity_sold: Option<u32>,
+    #[serde(default)]
+    pub last_synced: Option<u64>, // unix seconds, set by LocalSession::mark_synced
+    #[serde(default)]
+    pub brand: Option<String>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub colors: Vec<String>, // primary first, then secondary
+    #[serde(default)]
+    pub tags: Vec<String>, // normalized, vendoo Tags and Labels together, see public_tags
+    #[serde(default, with = "money::empty_as_none")]
+    pub sale_price: Option<Money>, // None for no sale
+    #[serde(default)]
+    pub cost_of_goods: Option<Money>, // vendoo only, the floor for PriceRules
+    #[serde(default)]
+    pub pricing: Option<PriceTrace>, // set by PriceRules::apply when it touched the price
+}
+
+// what happens to a WooCommerce product once its vendoo listing is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelistAction {
+    OutOfStock,
+    Draft,
+    Trash,
+}
+
+impl DelistAction {
+    pub fn from_str(str: &str) -> Option<Self> {
+        match str.trim().to_lowercase().as_str() {
+            "outofstock" | "out_of_stock" | "out-of-stock" => Some(DelistAction::OutOfStock),
+            "draft" => Some(DelistAction::Draft),
+            "trash" => Some(DelistAction::Trash),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DelistAction::OutOfStock => "outofstock",
+            DelistAction::Draft => "draft",
+            DelistAction::Trash => "trash",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DelistPolicy {
+    pub action: DelistAction,
+    // also delist WooCommerce products with no vendoo row at all. off by default since
+    // anything added to the store by hand has no vendoo row either.
+    pub include_missing: bool,
+}
+
+impl Default for DelistPolicy {
+    fn default() -> Self {
+        Self {
+            action: DelistAction::OutOfStock,
+            include_missing: false,
+        }
+    }
+}
+
+impl DelistPolicy {
+    // DELIST_ACTION=outofstock|draft|trash, DELIST_MISSING=true|false. without
+    // DELIST_ACTION it follows whatever STATUS_MAP does with Sold.
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(mapping) = status_map().to_wc("Sold") {
+            let action = match mapping.status.as_deref() {
+                Some("trash") => Some(DelistAction::Trash),
+                Some("draft") | Some("pending") | Some("private") => Some(DelistAction::Draft),
+                _ if mapping.stock_status.as_deref() == Some("outofstock") => {
+                    Some(DelistAction::OutOfStock)
+                }
+                _ => None,
+            };
+            if let Some(action) = action {
+                policy.action = action;
+            }
+        }
+        if let Ok(str) = std::env::var("DELIST_ACTION") {
+            match DelistAction::from_str(&str) {
+                Some(action) => policy.action = action,
+                // keeps the action STATUS_MAP gave it, so name that one
+                None => eprintln!(
+                    "[] DELIST_ACTION {} not understood, using {}",
+                    str,
+                    policy.action.as_str()
+                ),
+            }
+        }
+        if let Ok(str) = std::env::var("DELIST_MISSING") {
+            policy.include_missing = matches!(str.trim().to_lowercase().as_str(), "1" | "true");
+        }
+        policy
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DelistReason {
+    Sold {
+        date: Option<String>,
+        platform: Option<String>,
+        quantity: Option<u32>,
+    },
+    Archived {
+        status: String,
+    }, // the vendoo status
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delisting {
+    pub wc_id: u64,
+    pub name: String,
+    pub sku: String,
+    pub reason: DelistReason,
+    pub action: DelistAction,
+}
+
+impl DelistReason {
+    pub fn debug(&self) -> String {
+        match self {
+            DelistReason::Sold {
+                date,
+                platform,
+                quantity,
+            } => format!(
+                "sold {} on {} ({})",
+                quantity.unwrap_or(1),
+                platform.clone().unwrap_or(String::from("?")),
+                date.clone().unwrap_or(String::from("no date"))
+            ),
+            DelistReason::Archived { status } => format!("vendoo status {}", status),
+            DelistReason::Missing => String::from("not in the vendoo CSV"),
+        }
+    }
+}
+
+impl Delisting {
+    pub fn debug(&self) -> String {
+        format!(
+            "DELIST #{} {} (SKU: {}) -> {:?}: {}",
+            self.wc_id,
+            self.name,
+            self.sku,
+            self.action,
+            self.reason.debug()
+        )
+    }
+}
+
+// a matched product whose Vendoo side has changed, patch is ready for PUT or /products/batch
+#[derive(Debug, Clone, Serialize)]
+pub struct ProductUpdate {
+    pub wc_id: u64,
+    pub name: String,
+    pub sku: String,
+    pub changed: Vec<ChangedField>,
+    pub patch: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedField {
+    pub field: &'static str,
+    pub wc: String, // what the store has
+    pub vd: String, // what vendoo says
+}
+
+impl ProductUpdate {
+    pub fn debug(&self) -> String {
+        let mut str = format!("UPDATE #{} {} (SKU: {})\n", self.wc_id, self.name, self.sku);
+        for change in &self.changed {
+            str.push_str(&format!(
+                "    {}: {:?} -> {:?}\n",
+                change.field, change.wc, change.vd
+            ));
+        }
+        str
+    }
 }
 
 impl LocalObject {
     //converts vendoo object to local object
     pub fn from_vendoo_object(vprod: &VendooProduct) -> Self {
         let sig: Sig = Sig::VD;
-        let images = vprod.images.clone().unwrap_or(String::new());
-        let images: Vec<String> = vec![images];
+        let images: Vec<String> = vprod.image_urls();
         let name = vprod.title.clone().unwrap_or(String::new());
-        let description = vprod.description.clone().unwrap_or(String::new());
+        let description = description_templates().description(vprod);
+        let short_description = description_templates().short_description(vprod);
         let sku = vprod.sku.clone().unwrap_or(String::new());
         let category = vprod.category.clone().unwrap_or(String::new());
-        let price = vprod.price.clone().unwrap_or(0.0);
         let status = vprod.status.clone().unwrap_or(String::new());
         let mut stock_qty: Option<u32> = Some(vprod.quantity_left.clone().unwrap_or(0));
         if stock_qty == Some(0) {
             stock_qty = None
         }
 
-        let mut hash_hex = String::new();
-
-        if name.is_empty() {
-            hash_hex.push_str("NO TITLE, NO HASH ID");
-        } else {
-            let mut hasher = Sha256::new();
-            hasher.update(name.clone());
-            let res = hasher.finalize();
-            hash_hex = hex::encode(res);
-        }
+        let hash_hex = hash_title(&name);
+        let source_id = source_id_for_images(&images);
 
         Self {
             sig,
             hash_hex,
+            wc_id: None,
+            source_id,
             name: name,
-            regular_price: price.to_string(),
+            regular_price: vprod.price,
             description,
+            short_description,
             categories: category,
             images: images,
             stock_quantity: stock_qty,
+            stock_status: None,
             status,
             sku,
+            sold_date: vprod.sold_date.clone(),
+            sold_platform: vprod.sold_platform.clone(),
+            quantity_sold: vprod.quantity_sold,
+            last_synced: None,
+            brand: non_empty(&vprod.brand),
+            condition: non_empty(&vprod.condition),
+            colors: [&vprod.primary_color, &vprod.secondary_color]
+                .into_iter()
+                .filter_map(non_empty)
+                .collect(),
+            tags: split_tags(&[&vprod.tags, &vprod.labels]),
+            sale_price: None,
+            cost_of_goods: vprod.cost_of_goods,
+            pricing: None,
         }
     }
 
     pub fn from_woocommerce_object(wprod: &WooCommerceProduct) -> Self {
         // converts wc object to localobject
         let sig: Sig = Sig::WC;
+        let mut wc_images: Vec<Image> = wprod.images.clone();
+        wc_images.sort_by_key(|image| image.position.unwrap_or(u32::MAX));
         let mut images: Vec<String> = Vec::new();
-        for image in wprod.images.clone() {
+        for image in wc_images {
             images.push(image.src)
         }
         let name = wprod.name.clone();
-        let regular_price = wprod.regular_price.clone();
+        let regular_price = wprod.regular_price;
         let description = wprod.description.clone();
         let mut categories: String = String::new();
         for category in wprod.categories.clone() {
@@ -225,28 +595,46 @@ impl LocalObject {
         let status = wprod.status.clone();
         let sku = wprod.sku.clone();
 
-        let mut hash_hex: String = String::new();
-
-        if name.is_empty() {
-            hash_hex.push_str("NO TITLE, NO HASH ID");
-        } else {
-            let mut hasher = Sha256::new();
-            hasher.update(name.clone());
-            let res = hasher.finalize();
-            hash_hex = hex::encode(res);
-        }
+        let hash_hex = hash_title(&name);
+        let source_id = wprod
+            .meta_data
+            .iter()
+            .find(|meta| meta.key == SOURCE_ID_META_KEY)
+            .and_then(|meta| meta.value.as_str())
+            .map(|value| value.to_owned());
 
         Self {
             sig,
             hash_hex,
+            wc_id: wprod.id,
+            source_id,
             name: name,
             regular_price,
             description,
+            short_description: wprod.short_description.clone(),
             categories,
             images: images,
             stock_quantity: stock_qty,
+            stock_status: wprod.stock_status.clone(),
             status,
             sku,
+            sold_date: None,
+            sold_platform: None,
+            quantity_sold: None,
+            last_synced: None,
+            brand: attribute_options(wprod, BRAND_ATTRIBUTE).into_iter().next(),
+            condition: attribute_options(wprod, CONDITION_ATTRIBUTE)
+                .into_iter()
+                .next(),
+            colors: attribute_options(wprod, COLOR_ATTRIBUTE),
+            tags: wprod
+                .tags
+                .iter()
+                .map(|tag| normalize_term(&tag.name))
+                .collect(),
+            sale_price: wprod.sale_price,
+            cost_of_goods: None,
+            pricing: None,
         }
     }
 
@@ -275,28 +663,339 @@ impl LocalObject {
 
     pub fn to_woocommerce_object(&mut self) -> WooCommerceProduct {
         let mut images: Vec<Image> = Vec::new();
-        for str in self.images.clone() {
-            let image = Image { src: str };
+        for (position, str) in self.images.clone().into_iter().enumerate() {
+            let image = Image {
+                id: None,
+                src: str,
+                position: Some(position as u32),
+            };
             images.push(image);
         }
+        // names only, ObjWooCommerce::resolve_product_categories turns them into ids
         let mut categories: Vec<Category> = Vec::new();
-        let split: Vec<String> = self.categories.split(',').map(|s| s.to_string()).collect();
+        let split: Vec<String> = self
+            .categories
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
         for str in split {
-            let category = Category { name: str };
+            let category = Category {
+                id: None,
+                name: str,
+            };
             categories.push(category)
         }
 
+        let mut meta_data: Vec<MetaData> = Vec::new();
+        if let Some(source_id) = &self.source_id {
+            meta_data.push(MetaData {
+                id: None,
+                key: String::from(SOURCE_ID_META_KEY),
+                value: serde_json::json!(source_id),
+            });
+        }
+
+        // vendoo statuses go through STATUS_MAP, WooCommerce ones are already spelled right
+        let (status, stock_status) = match self.sig {
+            Sig::VD => match status_map().to_wc(&self.status) {
+                Some(mapping) => (
+                    mapping.status.clone().unwrap_or(String::from("draft")),
+                    mapping.stock_status.clone(),
+                ),
+                None => (String::from("draft"), None),
+            },
+            Sig::WC => (self.status.clone(), self.stock_status.clone()),
+        };
+
         WooCommerceProduct {
+            id: self.wc_id,
             name: self.name.clone(),
-            regular_price: self.regular_price.clone(),
+            regular_price: self.regular_price,
+            sale_price: self.sale_price,
             description: self.description.clone(),
+            short_description: self.short_description.clone(),
             categories,
             images,
             stock_quantity: self.stock_quantity,
-            status: self.status.clone(),
+            stock_status,
+            status,
             sku: self.sku.clone(),
+            attributes: self.attributes(),
+            tags: self
+                .public_tags()
+                .into_iter()
+                .map(|name| Tag { id: None, name })
+                .collect(),
+            meta_data,
+        }
+    }
+
+    // tags minus anything on TAG_DENYLIST
+    pub fn public_tags(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|tag| is_public_tag(tag))
+            .cloned()
+            .collect()
+    }
+
+    // brand, condition and colors as name-only product attributes, see
+    // ObjWooCommerce::resolve_product_attributes for the global ids
+    pub fn attributes(&self) -> Vec<ProductAttribute> {
+        let mut attributes: Vec<ProductAttribute> = Vec::new();
+        let fields: [(&str, Vec<String>); 3] = [
+            (BRAND_ATTRIBUTE, self.brand.clone().into_iter().collect()),
+            (
+                CONDITION_ATTRIBUTE,
+                self.condition.clone().into_iter().collect(),
+            ),
+            (COLOR_ATTRIBUTE, self.colors.clone()),
+        ];
+        for (name, options) in fields {
+            if options.is_empty() {
+                continue;
+            }
+            attributes.push(ProductAttribute {
+                id: None,
+                name: String::from(name),
+                position: attributes.len() as u32,
+                visible: true,
+                variation: false,
+                options,
+            });
+        }
+        attributes
+    }
+    pub fn has_title(&self) -> bool {
+        self.hash_hex != NO_TITLE_HASH
+    }
+
+    pub fn is_sold(&self) -> bool {
+        self.status.trim().eq_ignore_ascii_case("sold")
+    }
+
+    pub fn is_archived(&self) -> bool {
+        matches!(
+            self.status.trim().to_lowercase().as_str(),
+            "archived" | "delisted" | "deleted" | "inactive"
+        )
+    }
+
+    // vendoo side, why its WooCommerce product should come down (None if it shouldn't)
+    pub fn delist_reason(&self) -> Option<DelistReason> {
+        if self.is_sold() {
+            Some(DelistReason::Sold {
+                date: self.sold_date.clone(),
+                platform: self.sold_platform.clone(),
+                quantity: self.quantity_sold,
+            })
+        } else if self.is_archived() {
+            Some(DelistReason::Archived {
+                status: self.status.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    // WooCommerce side, "publish/outofstock (Sold)" with the vendoo status STATUS_MAP
+    // reads it as, for reports
+    pub fn status_report(&self) -> String {
+        let mut str = self.status.clone();
+        if let Some(stock_status) = &self.stock_status {
+            str.push_str(&format!("/{}", stock_status));
+        }
+        if let Some(vendoo) = status_map().to_vendoo(&self.status, self.stock_status.as_deref()) {
+            str.push_str(&format!(" ({})", vendoo));
+        }
+        str
+    }
+
+    // WooCommerce side, true if the delist action has already been applied
+    pub fn is_delisted_as(&self, action: DelistAction) -> bool {
+        match action {
+            DelistAction::OutOfStock => self.stock_status.as_deref() == Some("outofstock"),
+            DelistAction::Draft => self.status == "draft",
+            DelistAction::Trash => self.status == "trash",
         }
     }
+
+    // self is the vendoo side, wp_object the matching WooCommerce product
+    pub fn diff_against(&self, wp_object: &LocalObject, wc_id: u64) -> Option<ProductUpdate> {
+        let mut changed: Vec<ChangedField> = Vec::new();
+        let mut patch = serde_json::Map::new();
+        patch.insert(String::from("id"), serde_json::json!(wc_id));
+
+        if self.regular_price != wp_object.regular_price {
+            changed.push(ChangedField {
+                field: "regular_price",
+                wc: price_str(&wp_object.regular_price),
+                vd: price_str(&self.regular_price),
+            });
+            patch.insert(
+                String::from("regular_price"),
+                serde_json::json!(price_str(&self.regular_price)),
+            );
+        }
+
+        if self.sale_price != wp_object.sale_price {
+            changed.push(ChangedField {
+                field: "sale_price",
+                wc: price_str(&wp_object.sale_price),
+                vd: price_str(&self.sale_price),
+            });
+            patch.insert(
+                String::from("sale_price"),
+                serde_json::json!(price_str(&self.sale_price)),
+            );
+        }
+
+        if self.stock_quantity != wp_object.stock_quantity {
+            changed.push(ChangedField {
+                field: "stock_quantity",
+                wc: format!("{}", wp_object.stock_quantity.unwrap_or(0)),
+                vd: format!("{}", self.stock_quantity.unwrap_or(0)),
+            });
+            patch.insert(String::from("manage_stock"), serde_json::json!(true));
+            patch.insert(
+                String::from("stock_quantity"),
+                serde_json::json!(self.stock_quantity.unwrap_or(0)),
+            );
+        }
+
+        // compared (and shown) as text, the patch carries the HTML
+        let (wc_description, vd_description) = (
+            html::comparable(&wp_object.description),
+            html::comparable(&self.description),
+        );
+        if vd_description != wc_description {
+            changed.push(ChangedField {
+                field: "description",
+                wc: wc_description,
+                vd: vd_description,
+            });
+            patch.insert(
+                String::from("description"),
+                serde_json::json!(self.description),
+            );
+        }
+
+        let (wc_short, vd_short) = (
+            html::comparable(&wp_object.short_description),
+            html::comparable(&self.short_description),
+        );
+        if vd_short != wc_short {
+            changed.push(ChangedField {
+                field: "short_description",
+                wc: wc_short,
+                vd: vd_short,
+            });
+            patch.insert(
+                String::from("short_description"),
+                serde_json::json!(self.short_description),
+            );
+        }
+
+        // WooCommerce rehosts images so the src never matches, compare file names instead
+        let same_images = self.images.len() == wp_object.images.len()
+            && self
+                .images
+                .iter()
+                .zip(&wp_object.images)
+                .all(|(vd_src, wc_src)| same_image(vd_src, wc_src));
+        if !same_images {
+            changed.push(ChangedField {
+                field: "images",
+                wc: format!("{:?}", wp_object.images),
+                vd: format!("{:?}", self.images),
+            });
+            let images: Vec<serde_json::Value> = self
+                .images
+                .iter()
+                .enumerate()
+                .map(|(position, src)| serde_json::json!({ "src": src, "position": position }))
+                .collect();
+            patch.insert(String::from("images"), serde_json::Value::Array(images));
+        }
+
+        // WooCommerce replaces the whole attributes list on update, so any difference
+        // sends all of ours
+        let vd_attributes = attribute_key(&self.brand, &self.condition, &self.colors);
+        let wc_attributes =
+            attribute_key(&wp_object.brand, &wp_object.condition, &wp_object.colors);
+        if vd_attributes != wc_attributes {
+            changed.push(ChangedField {
+                field: "attributes",
+                wc: format!("{:?}", wc_attributes),
+                vd: format!("{:?}", vd_attributes),
+            });
+            patch.insert(
+                String::from("attributes"),
+                serde_json::to_value(self.attributes()).unwrap_or_default(),
+            );
+        }
+
+        // tag order means nothing to WooCommerce
+        let mut vd_tags = self.public_tags();
+        let mut wc_tags = wp_object.tags.clone();
+        vd_tags.sort();
+        wc_tags.sort();
+        if vd_tags != wc_tags {
+            changed.push(ChangedField {
+                field: "tags",
+                wc: wc_tags.join(", "),
+                vd: vd_tags.join(", "),
+            });
+            let tags: Vec<serde_json::Value> = vd_tags
+                .iter()
+                .map(|name| serde_json::json!({ "name": name }))
+                .collect();
+            patch.insert(String::from("tags"), serde_json::Value::Array(tags));
+        }
+
+        if let Some(mapping) = status_map().to_wc(&self.status) {
+            if let Some(status) = &mapping.status {
+                if *status != wp_object.status {
+                    changed.push(ChangedField {
+                        field: "status",
+                        wc: wp_object.status_report(),
+                        vd: format!("{} ({})", self.status, status),
+                    });
+                    patch.insert(String::from("status"), serde_json::json!(status));
+                }
+            }
+            // with a stock quantity WooCommerce works the stock status out itself
+            if let Some(stock_status) = &mapping.stock_status {
+                if self.stock_quantity.is_none()
+                    && wp_object.stock_status.as_ref() != Some(stock_status)
+                {
+                    changed.push(ChangedField {
+                        field: "stock_status",
+                        wc: wp_object.stock_status.clone().unwrap_or_default(),
+                        vd: format!("{} ({})", self.status, stock_status),
+                    });
+                    patch.insert(
+                        String::from("stock_status"),
+                        serde_json::json!(stock_status),
+                    );
+                }
+            }
+        }
+
+        if changed.is_empty() {
+            return None;
+        }
+
+        Some(ProductUpdate {
+            wc_id,
+            name: wp_object.name.clone(),
+            sku: wp_object.sku.clone(),
+            changed,
+            patch: serde_json::Value::Object(patch),
+        })
+    }
+
     pub fn debug(&self) -> String {
         let str = format!(
             "SIG: {},
@@ -320,8 +1019,8 @@ SKU: {}
             },
             self.hash_hex,
             self.name,
-            self.regular_price,
-            self.description,
+            price_str(&self.regular_price),
+            html::to_text(&self.description),
             self.categories,
             self.images,
             self.stock_quantity.unwrap_or(0),
@@ -344,3 +1043,293 @@ SKU: {}
 
      */
 }
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub const NO_TITLE_HASH: &str = "NO TITLE, NO HASH ID";
+// WooCommerce meta key holding the vendoo source id. no leading underscore, WordPress
+// hides those from the REST API.
+pub const SOURCE_ID_META_KEY: &str = "hcrelay_source_id";
+
+// "Vintage  Levi’s 501 " and "vintage levi's 501" hash the same
+pub fn hash_title(name: &str) -> String {
+    let normalized: String = name
+        .replace(['\u{2018}', '\u{2019}'], "'")
+        .replace(['\u{201C}', '\u{201D}'], "\"")
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    if normalized.is_empty() {
+        return String::from(NO_TITLE_HASH);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(normalized);
+    let res = hasher.finalize();
+    hex::encode(res)
+}
+
+// vendoo has no listing id in the CSV, but its image URLs are unique per upload and don't
+// change when the listing is renamed, so the first one stands in for it. images is the
+// list out of VendooProduct::image_urls, already split and checked.
+pub fn source_id_for_images(images: &[String]) -> Option<String> {
+    let first = images
+        .iter()
+        .map(|src| src.trim())
+        .find(|src| !src.is_empty())?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(first);
+    let res = hasher.finalize();
+    Some(hex::encode(res))
+}
+
+// WooCommerce's spelling of a price, "" clears it
+fn price_str(price: &Option<Money>) -> String {
+    price.map(|price| price.to_string()).unwrap_or_default()
+}
+
+// the lowercased file name without its extension and without the suffixes WordPress
+// adds to every upload of a photo: -scaled for big originals, -800x600 for resized copies
+fn image_key(src: &str) -> String {
+    let file = src
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(src)
+        .rsplit('/')
+        .next()
+        .unwrap_or(src)
+        .to_lowercase();
+    let stem = match file.rsplit_once('.') {
+        Some((stem, _ext)) => stem.to_owned(),
+        None => file,
+    };
+    if let Some(base) = stem.strip_suffix("-scaled") {
+        return base.to_owned();
+    }
+    match stem.rsplit_once('-') {
+        Some((base, size)) if is_size_suffix(size) => base.to_owned(),
+        _ => stem,
+    }
+}
+
+// "800x600"
+fn is_size_suffix(str: &str) -> bool {
+    match str.split_once('x') {
+        Some((width, height)) => is_number(width) && is_number(height),
+        None => false,
+    }
+}
+
+fn is_number(str: &str) -> bool {
+    !str.is_empty() && str.chars().all(|c| c.is_ascii_digit())
+}
+
+// https://cdn/x/IMG_0042.jpg and https://store/wp-content/uploads/IMG_0042-1.jpg are the
+// same photo, WordPress numbers a file whose name is already taken. jacket-1.jpg and
+// jacket-2.jpg are two photos though, so the number only counts on top of the vendoo name.
+fn same_image(vd_src: &str, wc_src: &str) -> bool {
+    let vd_key = image_key(vd_src);
+    let wc_key = image_key(wc_src);
+    if vd_key == wc_key {
+        return true;
+    }
+    match wc_key
+        .strip_prefix(vd_key.as_str())
+        .and_then(|rest| rest.strip_prefix('-'))
+    {
+        Some(n) => is_number(n),
+        None => false,
+    }
+}
+
+fn non_empty(field: &Option<String>) -> Option<String> {
+    field
+        .as_ref()
+        .map(|str| str.trim().to_owned())
+        .filter(|str| !str.is_empty())
+}
+
+// the options of the named attribute on a WooCommerce product, empty if it isn't there
+fn attribute_options(wprod: &WooCommerceProduct, name: &str) -> Vec<String> {
+    wprod
+        .attributes
+        .iter()
+        .find(|attribute| normalize_term(&attribute.name) == normalize_term(name))
+        .map(|attribute| attribute.options.clone())
+        .unwrap_or_default()
+}
+
+fn attribute_key(
+    brand: &Option<String>,
+    condition: &Option<String>,
+    colors: &[String],
+) -> (String, String, Vec<String>) {
+    (
+        normalize_term(brand.as_deref().unwrap_or("")),
+        normalize_term(condition.as_deref().unwrap_or("")),
+        colors.iter().map(|color| normalize_term(color)).collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::retry::random_u64;
+
+    fn vendoo(sku: &str, title: &str) -> LocalObject {
+        let product: VendooProduct = serde_json::from_value(serde_json::json!({
+            "Sku": sku,
+            "Title": title,
+            "Status": "Active",
+            "Price": "12.50",
+        }))
+        .unwrap();
+        LocalObject::from_vendoo_object(&product)
+    }
+
+    fn woocommerce(wc_id: u64, sku: &str, title: &str) -> LocalObject {
+        let product: WooCommerceProduct = serde_json::from_value(serde_json::json!({
+            "id": wc_id,
+            "name": title,
+            "regular_price": "12.50",
+            "description": "",
+            "categories": [],
+            "images": [],
+            "stock_quantity": null,
+            "status": "publish",
+            "sku": sku,
+        }))
+        .unwrap();
+        LocalObject::from_woocommerce_object(&product)
+    }
+
+    // a file under the system temp dir nothing else will pick, removed by the caller
+    fn temp_path() -> String {
+        std::env::temp_dir()
+            .join(format!("hcrelay-session-{}.json", random_u64()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn save_then_load_round_trips() {
+        let mut session = LocalSession::from_local_products(vec![
+            vendoo("A-1", "Shirt"),
+            woocommerce(7, "A-1", "Shirt"),
+        ]);
+        session.local_wp[0].last_synced = Some(1_700_000_000);
+        let path = temp_path();
+
+        session.save(&path).unwrap();
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["schema_version"], SESSION_SCHEMA_VERSION);
+        assert!(json["saved_at"].as_u64().unwrap() > 0);
+
+        let loaded = LocalSession::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.n, 2);
+        assert_eq!(loaded.local_vp.len(), 1);
+        assert_eq!(loaded.local_vp[0].sku, "A-1");
+        assert_eq!(
+            loaded.local_vp[0].regular_price,
+            session.local_vp[0].regular_price
+        );
+        assert_eq!(loaded.local_wp[0].wc_id, Some(7));
+        assert_eq!(loaded.local_wp[0].last_synced, Some(1_700_000_000));
+    }
+
+    #[test]
+    fn bare_arrays_still_load() {
+        let objects = vec![
+            vendoo("A-1", "Shirt"),
+            woocommerce(7, "A-1", "Shirt"),
+            vendoo("A-2", "Hat"),
+        ];
+        let path = temp_path();
+        std::fs::write(&path, serde_json::to_string(&objects).unwrap()).unwrap();
+
+        let loaded = LocalSession::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.n, 3);
+        let skus: Vec<&str> = loaded
+            .local_vp
+            .iter()
+            .map(|object| object.sku.as_str())
+            .collect();
+        assert_eq!(skus, vec!["A-1", "A-2"]);
+        assert_eq!(loaded.local_wp[0].wc_id, Some(7));
+    }
+
+    #[test]
+    fn newer_schemas_are_refused() {
+        let path = temp_path();
+        let json = serde_json::json!({
+            "schema_version": SESSION_SCHEMA_VERSION + 1,
+            "saved_at": 0,
+            "n": 0,
+            "local_vp": [],
+            "local_wp": [],
+            "current_idx": 0,
+        });
+        std::fs::write(&path, json.to_string()).unwrap();
+
+        let err = LocalSession::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("schema version"), "{}", err);
+    }
+
+    #[test]
+    fn wordpress_copies_are_the_same_image() {
+        let vd = "https://cdn.vendoo.co/u/IMG_0042.JPG?w=800";
+        for wc in [
+            "https://store/wp-content/uploads/2026/10/img_0042.jpg",
+            "https://store/wp-content/uploads/2026/10/IMG_0042-1.jpg",
+            "https://store/wp-content/uploads/2026/10/IMG_0042-scaled.jpg",
+            "https://store/wp-content/uploads/2026/10/IMG_0042-800x600.jpg",
+            "https://store/wp-content/uploads/2026/10/IMG_0042-2-scaled.jpg",
+        ] {
+            assert!(same_image(vd, wc), "{}", wc);
+        }
+        assert!(same_image(
+            "https://cdn/jacket-1.jpg",
+            "https://store/uploads/jacket-1-1.jpg"
+        ));
+    }
+
+    #[test]
+    fn numbered_photos_are_different_images() {
+        assert!(!same_image(
+            "https://cdn/jacket-2.jpg",
+            "https://store/uploads/jacket-1.jpg"
+        ));
+        assert!(!same_image(
+            "https://cdn/jacket.jpg",
+            "https://store/uploads/jacket-.jpg"
+        ));
+        assert!(!same_image(
+            "https://cdn/jacket.jpg",
+            "https://store/uploads/jacket-front.jpg"
+        ));
+
+        let mut vd = vendoo("A-1", "Jacket");
+        vd.images = vec![String::from("https://cdn/jacket-2.jpg")];
+        let mut wc = woocommerce(7, "A-1", "Jacket");
+        wc.images = vec![String::from("https://store/uploads/jacket-1.jpg")];
+        let update = vd.diff_against(&wc, 7).unwrap();
+        assert!(update
+            .changed
+            .iter()
+            .any(|changed| changed.field == "images"));
+    }
+}
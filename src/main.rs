@@ -1,8 +1,27 @@
+mod attributes;
+mod auth;
+mod categories;
+mod category_map;
+mod db;
+mod html;
+mod image_cache;
+mod image_process;
 mod local;
+mod matching;
+mod media;
+mod money;
 mod obj_vd;
 mod obj_wc;
+mod plan;
+mod pricing;
+mod retry;
 mod state;
+mod status_map;
+mod tags;
+mod template;
+mod upload;
 mod utils;
+mod wc_error;
 
 use dotenv::dotenv;
 use obj_vd::ObjVendoo;
@@ -29,6 +48,27 @@ pub struct BasicEnv {
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    // hcrelay --preview <sku>, prints the rendered descriptions and exits
+    if let Some(pos) = args.iter().position(|a| a == "-p" || a == "--preview") {
+        dotenv().ok();
+        let sku = match args.get(pos + 1) {
+            Some(sku) => sku,
+            None => {
+                println!("usage: hcrelay --preview <sku>");
+                std::process::exit(1);
+            }
+        };
+        let csv_path = env::var("CSV_PATH").expect("CSV_PATH not set");
+        match template::preview(&csv_path, sku) {
+            Ok(str) => println!("{}", str),
+            Err(e) => {
+                eprintln!("[] preview failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
     let mut file: String = String::new(); // csv file
 
     let idx: usize = 2; // magic number ik chill out
@@ -38,7 +78,7 @@ fn main() {
             match str.as_str() {
                 "-h" | "--help" => {
                     println!(
-                        "usage: hcrelay [-t | --testing] [-h | --help] [-f <path> | --file <path>]"
+                        "usage: hcrelay [-t | --testing] [-h | --help] [-f <path> | --file <path>] [-p <sku> | --preview <sku>]"
                     );
                     std::process::exit(0);
                     // false
@@ -68,7 +108,7 @@ fn main() {
             match str.as_str() {
                 "-h" | "--help" => {
                     println!(
-                        "usage: hcrelay [-t | --testing] [-h | --help] [-f <path> | --file <path>]"
+                        "usage: hcrelay [-t | --testing] [-h | --help] [-f <path> | --file <path>] [-p <sku> | --preview <sku>]"
                     );
                     std::process::exit(0);
                     // false
@@ -85,7 +125,7 @@ fn main() {
                 _ => {
                     println!("argument {} not understood", str);
                     println!(
-                        "usage: hcrelay [-t | --testing] [-h | --help] [-f <path> | --file <path>]"
+                        "usage: hcrelay [-t | --testing] [-h | --help] [-f <path> | --file <path>] [-p <sku> | --preview <sku>]"
                     );
                     std::process::exit(0);
                     // false
@@ -99,6 +139,11 @@ fn main() {
     let wc_api_url = env::var("WC_API_URL").expect("WC_API_URL not set");
     let wc_consumer_key = env::var("WC_CONSUMER_KEY").expect("WC_CONSUMER_KEY not set");
     let wc_consumer_secret = env::var("WC_CONSUMER_SECRET").expect("WC_CONSUMER_SECRET not set");
+    // images go up through /wp/v2/media, which refuses the WooCommerce keys. nothing else
+    // needs them, so without them media uploads just fall back to sideloading the URL.
+    if let Err(e) = auth::WpCredentials::from_env() {
+        eprintln!("[] {}, image URLs will be sideloaded instead", e);
+    }
     let mut csv_path: String = match env::var("CSV_PATH") {
         Ok(str) => {
             file = str.clone();
@@ -106,15 +151,16 @@ fn main() {
         }
         Err(_) => file.clone(),
     };
-    let local_db = env::var("LOCAL_DB").unwrap_or(String::from("no local db!"));
+    let local_db = env::var("LOCAL_DB").unwrap_or(String::from(crate::state::NO_LOCAL_DB));
 
     if csv_path != "".to_string() {
         csv_path = crate::state::CSV_PATH_FAILED.to_string();
     }
 
+    // no LOCAL_DB, no session file. never fall back to the CSV path, save would clobber it
     let mut local_db: Option<String> = match env::var("LOCAL_DB") {
         Ok(str) => Some(str),
-        Err(_) => Some(file.clone()),
+        Err(_) => None,
     };
 
     let basic_auth = BasicEnv {
@@ -123,7 +169,9 @@ fn main() {
         wc_sk: wc_consumer_secret.clone(),
 
         csv_path: csv_path.clone(),
-        json_path: local_db.clone().unwrap(),
+        json_path: local_db
+            .clone()
+            .unwrap_or(String::from(crate::state::NO_LOCAL_DB)),
     };
 
     // let logger_fn = |message: &str| println!("{:?}", message);
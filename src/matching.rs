@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::local::LocalObject;
+
+// how a vendoo product was paired with a WooCommerce product, strongest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKey {
+    Sku,
+    SourceId,
+    Title,
+}
+
+// indexes into LocalSession::local_vp / local_wp
+#[derive(Debug, Clone)]
+pub struct ProductMatch {
+    pub vp_idx: usize,
+    pub wp_idx: usize,
+    pub key: MatchKey,
+}
+
+#[derive(Debug, Clone)]
+pub enum AmbiguousMatch {
+    // the same SKU on several vendoo rows. wp_idx are the WooCommerce products carrying it,
+    // held back too so they don't look like they're missing from vendoo
+    DuplicateVendooSku {
+        sku: String,
+        vp_idx: Vec<usize>,
+        wp_idx: Vec<usize>,
+    },
+    // the same SKU on several WooCommerce products
+    DuplicateWooCommerceSku {
+        sku: String,
+        wp_idx: Vec<usize>,
+    },
+    // a title (with no SKU to go on) that maps to several WooCommerce products
+    AmbiguousTitle {
+        title: String,
+        vp_idx: usize,
+        wp_idx: Vec<usize>,
+    },
+    // a vendoo row that resolved to a WooCommerce product another row already claimed
+    AlreadyClaimed {
+        vp_idx: usize,
+        wp_idx: usize,
+        key: MatchKey,
+    },
+}
+
+impl AmbiguousMatch {
+    pub fn debug(&self, vp: &[LocalObject], wp: &[LocalObject]) -> String {
+        match self {
+            AmbiguousMatch::DuplicateVendooSku {
+                sku,
+                vp_idx,
+                wp_idx,
+            } => format!(
+                "SKU {} is on {} vendoo rows: {:?} (WooCommerce products {:?})",
+                sku,
+                vp_idx.len(),
+                vp_idx.iter().map(|idx| &vp[*idx].name).collect::<Vec<_>>(),
+                wp_idx.iter().map(|idx| wp[*idx].wc_id).collect::<Vec<_>>()
+            ),
+            AmbiguousMatch::DuplicateWooCommerceSku { sku, wp_idx } => format!(
+                "SKU {} is on {} WooCommerce products: {:?}",
+                sku,
+                wp_idx.len(),
+                wp_idx.iter().map(|idx| wp[*idx].wc_id).collect::<Vec<_>>()
+            ),
+            AmbiguousMatch::AmbiguousTitle {
+                title,
+                vp_idx: _,
+                wp_idx,
+            } => format!(
+                "title {:?} maps to {} WooCommerce products: {:?}",
+                title,
+                wp_idx.len(),
+                wp_idx.iter().map(|idx| wp[*idx].wc_id).collect::<Vec<_>>()
+            ),
+            AmbiguousMatch::AlreadyClaimed {
+                vp_idx,
+                wp_idx,
+                key,
+            } => format!(
+                "vendoo row {:?} matches WooCommerce product {:?} by {:?} but another row already did",
+                vp[*vp_idx].name, wp[*wp_idx].wc_id, key
+            ),
+        }
+    }
+
+    // vendoo rows that shouldn't be created or updated until a human sorts this out
+    fn vp_indices(&self) -> Vec<usize> {
+        match self {
+            AmbiguousMatch::DuplicateVendooSku { vp_idx, .. } => vp_idx.clone(),
+            AmbiguousMatch::DuplicateWooCommerceSku { .. } => Vec::new(),
+            AmbiguousMatch::AmbiguousTitle { vp_idx, .. } => vec![*vp_idx],
+            AmbiguousMatch::AlreadyClaimed { vp_idx, .. } => vec![*vp_idx],
+        }
+    }
+
+    // WooCommerce products that shouldn't be touched for the same reason
+    fn wp_indices(&self) -> Vec<usize> {
+        match self {
+            AmbiguousMatch::DuplicateVendooSku { wp_idx, .. } => wp_idx.clone(),
+            AmbiguousMatch::DuplicateWooCommerceSku { wp_idx, .. } => wp_idx.clone(),
+            AmbiguousMatch::AmbiguousTitle { wp_idx, .. } => wp_idx.clone(),
+            AmbiguousMatch::AlreadyClaimed { wp_idx, .. } => vec![*wp_idx],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MatchReport {
+    pub matches: Vec<ProductMatch>,
+    pub unmatched_vp: Vec<usize>, // vendoo rows with no WooCommerce product
+    pub unmatched_wp: Vec<usize>, // WooCommerce products with no vendoo row
+    pub ambiguous: Vec<AmbiguousMatch>,
+}
+
+impl MatchReport {
+    pub fn debug(&self, vp: &[LocalObject], wp: &[LocalObject]) -> String {
+        let count = |key: MatchKey| self.matches.iter().filter(|m| m.key == key).count();
+        let mut str = format!(
+            "--- MATCH REPORT ---
+MATCHED: {} (sku {}, source id {}, title {})
+UNMATCHED VENDOO: {}
+UNMATCHED WOOCOMMERCE: {}
+AMBIGUOUS: {}
+",
+            self.matches.len(),
+            count(MatchKey::Sku),
+            count(MatchKey::SourceId),
+            count(MatchKey::Title),
+            self.unmatched_vp.len(),
+            self.unmatched_wp.len(),
+            self.ambiguous.len()
+        );
+        for ambiguous in &self.ambiguous {
+            str.push_str(&format!("    {}\n", ambiguous.debug(vp, wp)));
+        }
+        str
+    }
+}
+
+// pairs vendoo rows with WooCommerce products. SKU wins, then the source id we stamp on
+// products we create, then the normalized title hash. anything that matches more than one
+// way ends up in ambiguous and is left out of matches and both unmatched lists.
+pub fn match_products(vp: &[LocalObject], wp: &[LocalObject]) -> MatchReport {
+    let mut report = MatchReport::default();
+
+    // by SKU in order, so the ambiguity report reads the same from run to run
+    let mut wp_by_sku: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    let mut wp_by_source: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut wp_by_title: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, wp_object) in wp.iter().enumerate() {
+        if let Some(sku) = normalize_sku(&wp_object.sku) {
+            wp_by_sku.entry(sku).or_default().push(idx);
+        }
+        if let Some(source_id) = wp_object.source_id.as_deref() {
+            wp_by_source.entry(source_id).or_default().push(idx);
+        }
+        if wp_object.has_title() {
+            wp_by_title
+                .entry(&wp_object.hash_hex)
+                .or_default()
+                .push(idx);
+        }
+    }
+
+    let mut vp_by_sku: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (idx, vp_object) in vp.iter().enumerate() {
+        if let Some(sku) = normalize_sku(&vp_object.sku) {
+            vp_by_sku.entry(sku).or_default().push(idx);
+        }
+    }
+
+    for (sku, wp_idx) in &wp_by_sku {
+        if wp_idx.len() > 1 {
+            report
+                .ambiguous
+                .push(AmbiguousMatch::DuplicateWooCommerceSku {
+                    sku: sku.clone(),
+                    wp_idx: wp_idx.clone(),
+                });
+        }
+    }
+    for (sku, vp_idx) in &vp_by_sku {
+        if vp_idx.len() > 1 {
+            report.ambiguous.push(AmbiguousMatch::DuplicateVendooSku {
+                sku: sku.clone(),
+                vp_idx: vp_idx.clone(),
+                wp_idx: wp_by_sku.get(sku).cloned().unwrap_or_default(),
+            });
+        }
+    }
+
+    let mut blocked_vp: HashSet<usize> = HashSet::new();
+    let mut blocked_wp: HashSet<usize> = HashSet::new();
+    for ambiguous in &report.ambiguous {
+        blocked_vp.extend(ambiguous.vp_indices());
+        blocked_wp.extend(ambiguous.wp_indices());
+    }
+
+    let mut resolved_vp: HashSet<usize> = HashSet::new();
+    let mut claimed_wp: HashSet<usize> = HashSet::new();
+
+    // one pass per key so a weak title match can't steal a product a later row matches by SKU
+    for key in [MatchKey::Sku, MatchKey::SourceId, MatchKey::Title] {
+        for (vp_idx, vp_object) in vp.iter().enumerate() {
+            if resolved_vp.contains(&vp_idx) || blocked_vp.contains(&vp_idx) {
+                continue;
+            }
+
+            let candidates: Vec<usize> = match key {
+                MatchKey::Sku => match normalize_sku(&vp_object.sku) {
+                    Some(sku) => wp_by_sku.get(&sku).cloned().unwrap_or_default(),
+                    None => Vec::new(),
+                },
+                MatchKey::SourceId => match vp_object.source_id.as_deref() {
+                    Some(source_id) => wp_by_source.get(source_id).cloned().unwrap_or_default(),
+                    None => Vec::new(),
+                },
+                MatchKey::Title => match vp_object.has_title() {
+                    // a product carrying a different SKU is a different item with the same title
+                    true => wp_by_title
+                        .get(vp_object.hash_hex.as_str())
+                        .cloned()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|idx| {
+                            let wp_sku = normalize_sku(&wp[*idx].sku);
+                            wp_sku.is_none() || wp_sku == normalize_sku(&vp_object.sku)
+                        })
+                        .collect(),
+                    false => Vec::new(),
+                },
+            };
+
+            let candidates: Vec<usize> = candidates
+                .into_iter()
+                .filter(|idx| !blocked_wp.contains(idx))
+                .collect();
+
+            match candidates.len() {
+                0 => {}
+                1 => {
+                    let wp_idx = candidates[0];
+                    resolved_vp.insert(vp_idx);
+                    if claimed_wp.insert(wp_idx) {
+                        report.matches.push(ProductMatch {
+                            vp_idx,
+                            wp_idx,
+                            key,
+                        });
+                    } else {
+                        report.ambiguous.push(AmbiguousMatch::AlreadyClaimed {
+                            vp_idx,
+                            wp_idx,
+                            key,
+                        });
+                    }
+                }
+                _ => {
+                    resolved_vp.insert(vp_idx);
+                    blocked_wp.extend(candidates.iter().copied());
+                    report.ambiguous.push(AmbiguousMatch::AmbiguousTitle {
+                        title: vp_object.name.clone(),
+                        vp_idx,
+                        wp_idx: candidates,
+                    });
+                }
+            }
+        }
+    }
+
+    // a product another row already claimed is still matched, just not twice
+    let ambiguous_vp: HashSet<usize> = report
+        .ambiguous
+        .iter()
+        .flat_map(|ambiguous| ambiguous.vp_indices())
+        .collect();
+    let ambiguous_wp: HashSet<usize> = report
+        .ambiguous
+        .iter()
+        .filter(|ambiguous| !matches!(ambiguous, AmbiguousMatch::AlreadyClaimed { .. }))
+        .flat_map(|ambiguous| ambiguous.wp_indices())
+        .collect();
+
+    report.unmatched_vp = (0..vp.len())
+        .filter(|idx| !resolved_vp.contains(idx) && !ambiguous_vp.contains(idx))
+        .collect();
+    report.unmatched_wp = (0..wp.len())
+        .filter(|idx| !claimed_wp.contains(idx) && !ambiguous_wp.contains(idx))
+        .collect();
+
+    report
+}
+
+pub fn normalize_sku(sku: &str) -> Option<String> {
+    let sku = sku.trim();
+    if sku.is_empty() {
+        None
+    } else {
+        Some(sku.to_uppercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::local::{source_id_for_images, SOURCE_ID_META_KEY};
+    use crate::obj_vd::VendooProduct;
+    use crate::obj_wc::WooCommerceProduct;
+
+    fn vendoo(sku: &str, title: &str, images: &str) -> LocalObject {
+        let product: VendooProduct = serde_json::from_value(serde_json::json!({
+            "Sku": sku,
+            "Title": title,
+            "Images": images,
+            "Status": "Active",
+        }))
+        .unwrap();
+        LocalObject::from_vendoo_object(&product)
+    }
+
+    fn woocommerce(wc_id: u64, sku: &str, name: &str, source_id: Option<&str>) -> LocalObject {
+        let meta_data = match source_id {
+            Some(source_id) => serde_json::json!([{"key": SOURCE_ID_META_KEY, "value": source_id}]),
+            None => serde_json::json!([]),
+        };
+        let product: WooCommerceProduct = serde_json::from_value(serde_json::json!({
+            "id": wc_id,
+            "name": name,
+            "regular_price": "10.00",
+            "description": "",
+            "categories": [],
+            "images": [],
+            "stock_quantity": null,
+            "status": "publish",
+            "sku": sku,
+            "meta_data": meta_data,
+        }))
+        .unwrap();
+        LocalObject::from_woocommerce_object(&product)
+    }
+
+    fn pairs(report: &MatchReport) -> Vec<(usize, usize, MatchKey)> {
+        let mut pairs: Vec<(usize, usize, MatchKey)> = report
+            .matches
+            .iter()
+            .map(|m| (m.vp_idx, m.wp_idx, m.key))
+            .collect();
+        pairs.sort_by_key(|(vp_idx, _, _)| *vp_idx);
+        pairs
+    }
+
+    #[test]
+    fn each_tier_matches() {
+        // a CDN transform URL with a comma in it is still one image
+        let first = "https://res.cloudinary.com/v/image/upload/w_800,h_800/a.jpg";
+        let source_id = source_id_for_images(&[first.to_owned()]).unwrap();
+        let vp = vec![
+            vendoo(" a-1 ", "Shirt", ""),
+            vendoo(
+                "",
+                "Renamed on vendoo",
+                &format!("{}, https://cdn.x/b.jpg", first),
+            ),
+            vendoo("", "Vintage  Levi’s 501", ""),
+            vendoo("", "Nowhere", ""),
+        ];
+        let wp = vec![
+            woocommerce(10, "Z-9", "Someone else's", None),
+            woocommerce(11, "", "vintage levi's 501", None),
+            woocommerce(12, "", "Old title", Some(&source_id)),
+            woocommerce(13, "A-1", "Different name", None),
+        ];
+        assert_eq!(vp[1].source_id.as_deref(), Some(source_id.as_str()));
+
+        let report = match_products(&vp, &wp);
+        assert_eq!(
+            pairs(&report),
+            vec![
+                (0, 3, MatchKey::Sku),
+                (1, 2, MatchKey::SourceId),
+                (2, 1, MatchKey::Title),
+            ]
+        );
+        assert_eq!(report.unmatched_vp, vec![3]);
+        assert_eq!(report.unmatched_wp, vec![0]);
+        assert!(report.ambiguous.is_empty());
+    }
+
+    #[test]
+    fn sku_wins_over_title() {
+        let vp = vec![vendoo("B-2", "Jacket", ""), vendoo("", "Jacket", "")];
+        let wp = vec![
+            woocommerce(20, "", "Jacket", None),
+            woocommerce(21, "b-2", "Coat", None),
+        ];
+        let report = match_products(&vp, &wp);
+        // the SKU row takes #21, which leaves #20 for the title-only row
+        assert_eq!(
+            pairs(&report),
+            vec![(0, 1, MatchKey::Sku), (1, 0, MatchKey::Title)]
+        );
+    }
+
+    #[test]
+    fn titles_dont_match_across_skus() {
+        let vp = vec![vendoo("C-1", "Hat", "")];
+        let wp = vec![woocommerce(30, "C-2", "Hat", None)];
+        let report = match_products(&vp, &wp);
+        assert!(report.matches.is_empty());
+        assert_eq!(report.unmatched_vp, vec![0]);
+        assert_eq!(report.unmatched_wp, vec![0]);
+    }
+
+    #[test]
+    fn ambiguous_titles_are_left_alone() {
+        let vp = vec![vendoo("", "Mug", ""), vendoo("", "Bowl", "")];
+        let wp = vec![
+            woocommerce(40, "", "Mug", None),
+            woocommerce(41, "", "mug", None),
+            woocommerce(42, "", "Bowl", None),
+        ];
+        let report = match_products(&vp, &wp);
+
+        assert_eq!(pairs(&report), vec![(1, 2, MatchKey::Title)]);
+        assert_eq!(report.ambiguous.len(), 1);
+        match &report.ambiguous[0] {
+            AmbiguousMatch::AmbiguousTitle { vp_idx, wp_idx, .. } => {
+                assert_eq!(*vp_idx, 0);
+                assert_eq!(wp_idx, &vec![0, 1]);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(report.unmatched_vp.is_empty());
+        assert!(report.unmatched_wp.is_empty());
+    }
+
+    #[test]
+    fn duplicate_skus_block_the_rows() {
+        let vp = vec![vendoo("D-1", "One", ""), vendoo("d-1", "Two", "")];
+        let wp = vec![woocommerce(50, "D-1", "One", None)];
+        let report = match_products(&vp, &wp);
+
+        assert!(report.matches.is_empty());
+        assert!(matches!(
+            &report.ambiguous[..],
+            [AmbiguousMatch::DuplicateVendooSku { vp_idx, wp_idx, .. }]
+                if vp_idx == &vec![0, 1] && wp_idx == &vec![0]
+        ));
+        assert!(report.unmatched_vp.is_empty());
+        // held back, not delisted as missing from the CSV
+        assert!(!report.unmatched_wp.contains(&0));
+    }
+
+    #[test]
+    fn ambiguities_come_out_in_sku_order() {
+        let vp = vec![
+            vendoo("Q-1", "Q", ""),
+            vendoo("q-1", "Q", ""),
+            vendoo("B-1", "B", ""),
+            vendoo("b-1", "B", ""),
+            vendoo("K-1", "K", ""),
+            vendoo("k-1", "K", ""),
+        ];
+        let wp = vec![
+            woocommerce(60, "Z-1", "Z", None),
+            woocommerce(61, "z-1", "Z", None),
+            woocommerce(62, "A-1", "A", None),
+            woocommerce(63, "a-1", "A", None),
+        ];
+        let skus: Vec<String> = match_products(&vp, &wp)
+            .ambiguous
+            .iter()
+            .map(|ambiguous| match ambiguous {
+                AmbiguousMatch::DuplicateVendooSku { sku, .. }
+                | AmbiguousMatch::DuplicateWooCommerceSku { sku, .. } => sku.clone(),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(skus, vec!["A-1", "Z-1", "B-1", "K-1", "Q-1"]);
+    }
+}
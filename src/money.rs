@@ -0,0 +1,236 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// an exact amount of money. vendoo's CSV has "$1,234.50" or 19.9, WooCommerce wants and
+// sends "19.90", and neither should ever go through an f64 on the way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Money(Decimal);
+
+impl Money {
+    pub const ZERO: Money = Money(Decimal::ZERO);
+
+    pub fn from_cents(cents: i64) -> Self {
+        Money(Decimal::new(cents, 2))
+    }
+
+    // rounded half away from zero, 19.995 -> 2000
+    pub fn cents(&self) -> i64 {
+        (self.round() * Decimal::ONE_HUNDRED)
+            .to_i64()
+            .unwrap_or(i64::MAX)
+    }
+
+    pub fn round(&self) -> Decimal {
+        self.0
+            .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+    }
+
+    pub fn decimal(&self) -> Decimal {
+        self.0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > Decimal::ZERO
+    }
+
+    // price * (1 + percent / 100), rounded to the cent
+    pub fn scaled(&self, percent: Decimal) -> Self {
+        let factor = Decimal::ONE + percent / Decimal::ONE_HUNDRED;
+        Money(self.0 * factor).rounded()
+    }
+
+    pub fn rounded(&self) -> Self {
+        Money(self.round())
+    }
+
+    pub fn parse(str: &str) -> Result<Self, String> {
+        let cleaned: String = str
+            .trim()
+            .chars()
+            .filter(|c| !matches!(c, '$' | ',' | ' ' | '_'))
+            .collect();
+        if cleaned.is_empty() {
+            return Err(format!("not an amount: {:?}", str));
+        }
+        Decimal::from_str(&cleaned)
+            .map(Money)
+            .map_err(|_| format!("not an amount: {:?}", str))
+    }
+}
+
+impl From<Decimal> for Money {
+    fn from(decimal: Decimal) -> Self {
+        Money(decimal)
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        Money::parse(str)
+    }
+}
+
+// WooCommerce's format, always two decimals and no separators: "1234.50"
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rounded = self.round();
+        rounded.rescale(2);
+        write!(f, "{}", rounded)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+// strings ("$1,234.50", "19.9") or plain numbers, a number goes through its shortest
+// decimal text so 19.9 stays 19.9 and doesn't become 19.899999...
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Str(String),
+            Int(i64),
+            Float(f64),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Str(str) => Money::parse(&str).map_err(serde::de::Error::custom),
+            Raw::Int(int) => Ok(Money(Decimal::from(int))),
+            Raw::Float(float) => Money::parse(&float.to_string()).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// for WooCommerce's price fields, which are "" rather than null when there's no price
+pub mod empty_as_none {
+    use super::Money;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        money: &Option<Money>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match money {
+            Some(money) => serializer.serialize_str(&money.to_string()),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Money>, D::Error> {
+        let raw: Option<serde_json::Value> = Option::deserialize(deserializer)?;
+        match raw {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(serde_json::Value::String(str)) if str.trim().is_empty() => Ok(None),
+            Some(serde_json::Value::String(str)) => Money::parse(&str)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            Some(serde_json::Value::Number(number)) => Money::parse(&number.to_string())
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            Some(other) => Err(serde::de::Error::custom(format!(
+                "not an amount: {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_vendoo_writes() {
+        assert_eq!(
+            Money::parse("$1,234.50").unwrap(),
+            Money::from_cents(123450)
+        );
+        assert_eq!(Money::parse(" 19.9 ").unwrap(), Money::from_cents(1990));
+        assert_eq!(Money::parse("$ 5").unwrap(), Money::from_cents(500));
+        assert_eq!(Money::parse("-3.25").unwrap(), Money::from_cents(-325));
+        assert!(Money::parse("").is_err());
+        assert!(Money::parse("$").is_err());
+        assert!(Money::parse("ten").is_err());
+        assert!(Money::parse("1.2.3").is_err());
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(Money::parse("19.995").unwrap().cents(), 2000);
+        assert_eq!(Money::parse("19.994").unwrap().cents(), 1999);
+        assert_eq!(Money::parse("-0.005").unwrap().cents(), -1);
+        assert_eq!(
+            Money::from_cents(1000).scaled(Decimal::new(125, 1)),
+            Money::from_cents(1125)
+        );
+        // 0.1 + 0.2 is exactly 0.3 here
+        assert_eq!(
+            Money::parse("0.1").unwrap() + Money::parse("0.2").unwrap(),
+            Money::parse("0.3").unwrap()
+        );
+    }
+
+    #[test]
+    fn serializes_the_way_woocommerce_wants() {
+        assert_eq!(Money::parse("$1,234.5").unwrap().to_string(), "1234.50");
+        assert_eq!(Money::parse("7").unwrap().to_string(), "7.00");
+        assert_eq!(Money::parse("2.499").unwrap().to_string(), "2.50");
+        assert_eq!(
+            serde_json::to_string(&Money::from_cents(1990)).unwrap(),
+            "\"19.90\""
+        );
+
+        let from_json = |json: &str| serde_json::from_str::<Money>(json).unwrap();
+        assert_eq!(from_json("\"$1,234.50\""), Money::from_cents(123450));
+        assert_eq!(from_json("19.9"), Money::from_cents(1990));
+        assert_eq!(from_json("20"), Money::from_cents(2000));
+    }
+
+    #[test]
+    fn empty_woocommerce_prices_are_none() {
+        #[derive(Serialize, Deserialize)]
+        struct Prices {
+            #[serde(with = "empty_as_none", default)]
+            sale_price: Option<Money>,
+        }
+
+        let prices: Prices = serde_json::from_str(r#"{"sale_price": ""}"#).unwrap();
+        assert_eq!(prices.sale_price, None);
+        assert_eq!(
+            serde_json::to_string(&prices).unwrap(),
+            r#"{"sale_price":""}"#
+        );
+        let prices: Prices = serde_json::from_str(r#"{"sale_price": "12.5"}"#).unwrap();
+        assert_eq!(prices.sale_price, Some(Money::from_cents(1250)));
+        assert!(serde_json::from_str::<Prices>(r#"{"sale_price": "free"}"#).is_err());
+    }
+}
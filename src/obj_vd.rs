@@ -1,10 +1,12 @@
 use csv::ReaderBuilder;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt::format;
 use std::fs::File;
 use std::path::Path;
 
+use crate::money::Money;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ObjVendoo {
     pub csv_path: Option<String>,                 // path to CSV
@@ -12,7 +14,7 @@ pub struct ObjVendoo {
     pub external_img: Option<Vec<ExternalImage>>, // urls for images, each with product ID
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct VendooProduct {
     #[serde(rename = "Images")]
     pub images: Option<String>,
@@ -45,7 +47,7 @@ pub struct VendooProduct {
     pub category: Option<String>,
 
     #[serde(rename = "Price")]
-    pub price: Option<f64>,
+    pub price: Option<Money>,
 
     #[serde(rename = "Status")]
     pub status: Option<String>,
@@ -69,16 +71,16 @@ pub struct VendooProduct {
     pub internal_notes: Option<String>,
 
     #[serde(rename = "Price Sold")]
-    pub price_sold: Option<f64>,
+    pub price_sold: Option<Money>,
 
     #[serde(rename = "Cost of Goods")]
-    pub cost_of_goods: Option<f64>,
+    pub cost_of_goods: Option<Money>,
 
     #[serde(rename = "Marketplace Fees")]
-    pub marketplace_fees: Option<f64>,
+    pub marketplace_fees: Option<Money>,
 
     #[serde(rename = "Shipping Expenses")]
-    pub shipping_expenses: Option<f64>,
+    pub shipping_expenses: Option<Money>,
 
     #[serde(rename = "Labels")]
     pub labels: Option<String>,
@@ -90,9 +92,18 @@ pub struct VendooProduct {
     pub quantity_sold: Option<u32>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// one vendoo image and where ImageCache put it, see ObjVendoo::cache_images
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ExternalImage {
     pub src: String,
+    #[serde(default)]
+    pub product: String, // sku, or the title when there isn't one
+    #[serde(default)]
+    pub position: u32, // 0 is the featured image
+    #[serde(default)]
+    pub sha256: Option<String>, // None if the download failed
+    #[serde(default)]
+    pub path: Option<String>,
 }
 
 impl ObjVendoo {
@@ -129,11 +140,13 @@ impl ObjVendoo {
 
         println!("[] successfully parsed {} of {} rows", y, x);
 
-        Ok(Self {
+        let vendoo = Self {
             csv_path: Some(path.to_owned()),
             products: Some(vendoo_products),
             external_img: None,
-        })
+        };
+        vendoo.report_rejected_images();
+        Ok(vendoo)
     }
 
     pub fn existing_from_csv(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -162,10 +175,33 @@ impl ObjVendoo {
         self.products = Some(vendoo_products);
 
         println!("[] successfully parsed {} of {} rows", y, x);
+        self.report_rejected_images();
 
         Ok(())
     }
 
+    // every image URL image_urls drops, printed together once per import instead of each
+    // time a product's images are read
+    fn report_rejected_images(&self) {
+        let mut rejected: Vec<String> = Vec::new();
+        for product in self.products.iter().flatten() {
+            for src in product.rejected_image_urls() {
+                rejected.push(format!(
+                    "    {}: {}",
+                    product.sku.clone().unwrap_or(String::from("(no sku)")),
+                    src
+                ));
+            }
+        }
+        if !rejected.is_empty() {
+            eprintln!(
+                "[] skipping {} invalid image URLs:\n{}",
+                rejected.len(),
+                rejected.join("\n")
+            );
+        }
+    }
+
     pub fn get_length(&self) -> i32 {
         let len = self.products.as_ref().unwrap().len() as i32;
         len
@@ -173,6 +209,23 @@ impl ObjVendoo {
 }
 
 impl VendooProduct {
+    // the Images cell as an ordered list, first one is the featured image. anything that
+    // isn't an http(s) URL gets dropped instead of becoming a broken image, the import
+    // reports those once (see ObjVendoo::from_csv).
+    pub fn image_urls(&self) -> Vec<String> {
+        match &self.images {
+            Some(cell) => parse_image_cell(cell).0,
+            None => Vec::new(),
+        }
+    }
+
+    pub fn rejected_image_urls(&self) -> Vec<String> {
+        match &self.images {
+            Some(cell) => parse_image_cell(cell).1,
+            None => Vec::new(),
+        }
+    }
+
     pub fn debug(&self) -> String {
         // debugs only what is relevant to WC and therefore LocalObject
         let images = self.images.clone().unwrap_or(String::new());
@@ -180,7 +233,7 @@ impl VendooProduct {
         let description = self.description.clone().unwrap_or(String::new());
         let sku = self.sku.clone().unwrap_or(String::new());
         let category = self.category.clone().unwrap_or(String::new());
-        let price = self.price.clone().unwrap_or(0.0);
+        let price = self.price.unwrap_or_default();
         let status = self.status.clone().unwrap_or(String::new());
         let stock_qty = self.quantity_left.clone().unwrap_or(0);
 
@@ -201,6 +254,51 @@ SERIAL: {}
     }
 }
 
+// splits an Images cell into (valid, rejected). vendoo separates URLs with commas, spaces,
+// newlines or pipes depending on where the export came from. CDN transform URLs can have
+// commas in them too ("w_800,h_800"), so a comma piece that doesn't start a new URL gets
+// glued back onto the one before it.
+pub fn parse_image_cell(cell: &str) -> (Vec<String>, Vec<String>) {
+    let mut pieces: Vec<String> = Vec::new();
+    for chunk in cell.split(|c: char| c.is_whitespace() || c == ';' || c == '|') {
+        // only glue onto a piece of this same chunk, ", " between two pieces is a separator
+        let mut last_in_chunk: Option<usize> = None;
+        for piece in chunk.split(',') {
+            let piece = piece.trim();
+            if piece.is_empty() {
+                continue;
+            }
+            let starts_url = piece.contains("://");
+            match last_in_chunk {
+                Some(idx) if !starts_url && pieces[idx].contains("://") => {
+                    pieces[idx].push(',');
+                    pieces[idx].push_str(piece);
+                }
+                _ => {
+                    pieces.push(piece.to_owned());
+                    last_in_chunk = Some(pieces.len() - 1);
+                }
+            }
+        }
+    }
+
+    let mut urls: Vec<String> = Vec::new();
+    let mut rejected: Vec<String> = Vec::new();
+    for piece in pieces {
+        match reqwest::Url::parse(&piece) {
+            Ok(url) if (url.scheme() == "https" || url.scheme() == "http") && url.has_host() => {
+                let url = url.to_string();
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+            _ => rejected.push(piece),
+        }
+    }
+
+    (urls, rejected)
+}
+
 /*
  VENDOO OBJ FIELDS
     pub images: Option<String>,         -- WC IMAGES[]
@@ -213,7 +311,7 @@ SERIAL: {}
     pub tags: Option<String>,
     pub sku: Option<String>,            -- WC SKU
     pub category: Option<String>,       -- WC CATEGORY[]
-    pub price: Option<f64>,             -- WC REGULAR PRICE
+    pub price: Option<Money>,           -- WC REGULAR PRICE
     pub status: Option<String>,         -- WC STATUS
     pub listed_date: Option<String>,
     pub sold_date: Option<String>,
@@ -221,11 +319,92 @@ SERIAL: {}
     pub listing_platforms: Option<String>,
     pub sold_platform: Option<String>,
     pub internal_notes: Option<String>,
-    pub price_sold: Option<f64>,
-    pub cost_of_goods: Option<f64>,
-    pub marketplace_fees: Option<f64>,
-    pub shipping_expenses: Option<f64>,
+    pub price_sold: Option<Money>,
+    pub cost_of_goods: Option<Money>,
+    pub marketplace_fees: Option<Money>,
+    pub shipping_expenses: Option<Money>,
     pub labels: Option<String>,
     pub quantity_left: Option<u32>,     -- WC STOCK_QTY
     pub quantity_sold: Option<u32>,
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_keep_their_order_across_separators() {
+        let (urls, rejected) = parse_image_cell(
+            "https://a.example/1.jpg, https://a.example/2.jpg;https://a.example/3.jpg|\
+             https://a.example/4.jpg\n  https://a.example/5.jpg",
+        );
+        assert_eq!(
+            urls,
+            (1..=5)
+                .map(|n| format!("https://a.example/{}.jpg", n))
+                .collect::<Vec<String>>()
+        );
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn cdn_commas_stay_in_the_url() {
+        let (urls, _) = parse_image_cell(
+            "https://res.cloudinary.com/v/image/upload/w_800,h_800/a.jpg,https://b.example/c.jpg",
+        );
+        assert_eq!(
+            urls,
+            vec![
+                "https://res.cloudinary.com/v/image/upload/w_800,h_800/a.jpg",
+                "https://b.example/c.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn anything_but_http_urls_is_rejected() {
+        let (urls, rejected) = parse_image_cell(
+            "ftp://a.example/1.jpg, IMG_0042.jpg, https://, file:///etc/passwd, \
+             javascript:alert(1), http://a.example/ok.jpg",
+        );
+        assert_eq!(urls, vec!["http://a.example/ok.jpg"]);
+        assert_eq!(
+            rejected,
+            vec![
+                "ftp://a.example/1.jpg",
+                "IMG_0042.jpg",
+                "https://",
+                "file:///etc/passwd",
+                "javascript:alert(1)",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicates_are_dropped_after_the_first() {
+        let (urls, _) = parse_image_cell(
+            "https://a.example/1.jpg, HTTPS://A.EXAMPLE/2.jpg, https://a.example/1.jpg, \
+             https://a.example/2.jpg",
+        );
+        assert_eq!(
+            urls,
+            vec!["https://a.example/1.jpg", "https://a.example/2.jpg"]
+        );
+    }
+
+    #[test]
+    fn products_split_valid_and_rejected_images() {
+        let product: VendooProduct = serde_json::from_value(serde_json::json!({
+            "Sku": "A-1",
+            "Images": "https://a.example/1.jpg, not-a-url",
+        }))
+        .unwrap();
+        assert_eq!(product.image_urls(), vec!["https://a.example/1.jpg"]);
+        assert_eq!(product.rejected_image_urls(), vec!["not-a-url"]);
+
+        let empty: VendooProduct =
+            serde_json::from_value(serde_json::json!({"Sku": "A-2"})).unwrap();
+        assert!(empty.image_urls().is_empty());
+        assert!(empty.rejected_image_urls().is_empty());
+    }
+}
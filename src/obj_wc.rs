@@ -1,8 +1,28 @@
-use base64::encode;
-use regex::Regex;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::attributes::AttributeCache;
+use crate::auth::{AuthStrategy, WpCredentials};
+use crate::categories::CategoryTree;
+use crate::html;
+use crate::image_cache::ImageCache;
+use crate::image_process::ImageProcessing;
+use crate::local::{DelistAction, Delisting, LocalObject, ProductUpdate};
+use crate::money::{self, Money};
+use crate::plan::SyncPlan;
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::status_map::{WC_STATUSES, WC_STOCK_STATUSES};
+use crate::tags::TagCache;
+use crate::wc_error::{self, WcError};
+
+// WooCommerce caps per_page at 100
+pub const WC_PER_PAGE: u32 = 100;
+// and /products/batch at 100 items per request (create + update + delete)
+pub const WC_BATCH_LIMIT: usize = 100;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ObjWooCommerce {
@@ -11,157 +31,825 @@ pub struct ObjWooCommerce {
     base_api: String,
     pub skey: String, // WC secret key
     pub ckey: String, // WC consumer key
+    #[serde(default)]
+    pub categories: Option<CategoryTree>, // fetched on first use, see categories.rs
+    #[serde(default)]
+    pub attributes: Option<AttributeCache>, // same, see attributes.rs
+    #[serde(default)]
+    pub tags: Option<TagCache>, // and tags.rs
+    #[serde(skip)]
+    pub image_cache: Option<ImageCache>, // when set, cached images go up as media, see media.rs
+    #[serde(skip)]
+    pub image_processing: Option<ImageProcessing>, // applied before each media upload
+    #[serde(skip)]
+    pub retry: RetryPolicy, // for every request, see retry.rs
+    #[serde(skip)]
+    pub(crate) client: Client, // one per store so connections get reused, see http_client
+    #[serde(skip)]
+    pub auth: AuthStrategy, // WC_AUTH, how requests are signed
+    #[serde(skip)]
+    pub wp_credentials: Option<WpCredentials>, // for /wp/v2, None means media gets sideloaded
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct WooCommerceProduct {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>, // assigned by WooCommerce, None until the product exists
     pub name: String,
-    pub regular_price: String,
+    #[serde(default, with = "money::empty_as_none")]
+    pub regular_price: Option<Money>, // "" on the wire when there's no price
+    #[serde(
+        default,
+        with = "money::empty_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sale_price: Option<Money>, // None when the product isn't on sale
     pub description: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub short_description: String,
     pub categories: Vec<Category>,
     pub images: Vec<Image>,
     pub stock_quantity: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stock_status: Option<String>, // instock, outofstock, onbackorder
     pub status: String,
     pub sku: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<ProductAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Tag>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meta_data: Vec<MetaData>,
+}
+
+// like Category, name-only until ObjWooCommerce::resolve_product_tags fills in the id
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tag {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default)]
+    pub name: String,
+}
+
+// an attribute as it sits on a product. with an id it points at a global attribute and
+// the options are its term names; without one WooCommerce makes a product-local text
+// attribute, which layered navigation can't filter on.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ProductAttribute {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub position: u32,
+    #[serde(default)]
+    pub visible: bool, // shown in the product page's additional information tab
+    #[serde(default)]
+    pub variation: bool,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MetaData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub key: String,
+    pub value: serde_json::Value,
+}
+
+// progress of a paginated catalog download, handed to the progress callback after each page
+#[derive(Debug, Clone, Copy)]
+pub struct FetchProgress {
+    pub page: u32,
+    pub total_pages: Option<u32>, // from X-WP-TotalPages, None if the store didn't send it
+    pub fetched: usize,
+    pub total: Option<usize>, // from X-WP-Total
+}
+
+impl FetchProgress {
+    pub fn debug(&self) -> String {
+        let total_pages = match self.total_pages {
+            Some(n) => n.to_string(),
+            None => String::from("?"),
+        };
+        let total = match self.total {
+            Some(n) => n.to_string(),
+            None => String::from("?"),
+        };
+        format!(
+            "page {} of {}, {} of {} products fetched",
+            self.page, total_pages, self.fetched, total
+        )
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Category {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>, // what WooCommerce reads on create, see resolve_product_categories
+    #[serde(default)]
     pub name: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Image {
+    // a media library id, set when media.rs uploaded the file. WooCommerce then ignores src.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub src: String,
+    // WooCommerce treats position 0 as the featured image, the rest are the gallery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u32>,
 }
 
 impl ObjWooCommerce {
     pub fn new_with_auth(base_api: String, ckey: String, skey: String) -> Self {
         let db_path: Option<String> = None;
         let products: Option<Vec<WooCommerceProduct>> = None;
+        let auth = AuthStrategy::from_env(&base_api);
+        let wp_credentials = WpCredentials::from_env().ok();
         Self {
             db_path,
             products,
             base_api,
             skey,
             ckey,
+            categories: None,
+            attributes: None,
+            tags: None,
+            image_cache: None,
+            image_processing: None,
+            retry: RetryPolicy::from_env(),
+            client: http_client(),
+            auth,
+            wp_credentials,
         }
     }
 
-    fn build_authorization_header(&self) -> String {
-        let auth = format!("{}:{}", self.ckey, self.skey);
-        let encoded_auth = encode(auth); // base64 encode
-        format!("Basic {}", encoded_auth)
+    // the same store without its caches, cheap to hand to spawned tasks. shares the
+    // connection pool with self.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            db_path: None,
+            products: None,
+            base_api: self.base_api.clone(),
+            skey: self.skey.clone(),
+            ckey: self.ckey.clone(),
+            categories: None,
+            attributes: None,
+            tags: None,
+            image_cache: None,
+            image_processing: None,
+            retry: self.retry.clone(),
+            client: self.client.clone(),
+            auth: self.auth.clone(),
+            wp_credentials: self.wp_credentials.clone(),
+        }
     }
 
-    // Fetches all products and populates self.products
-    pub async fn fetch_populate_products(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let url = format!(
-            "{}/wp-json/wc/v3/products",
-            self.base_api.trim_end_matches('/')
-        );
-        let client = Client::new();
+    // "products/batch" -> https://store/wp-json/wc/v3/products/batch
+    pub(crate) fn endpoint(&self, path: &str) -> String {
+        format!(
+            "{}/wp-json/wc/v3/{}",
+            self.base_api.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
 
-        let auth_header = self.build_authorization_header();
+    // "media" -> https://store/wp-json/wp/v2/media
+    pub(crate) fn wp_endpoint(&self, path: &str) -> String {
+        format!(
+            "{}/wp-json/wp/v2/{}",
+            self.base_api.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
 
-        let response = client
-            .get(&url)
-            .header("Authorization", auth_header) // Manually add the Basic Auth header
-            .send()
-            .await?;
+    // Fetches all products (every page) and populates self.products
+    pub async fn fetch_populate_products(&mut self) -> Result<(), WcError> {
+        let products = self.fetch_all_products(|_| {}).await?;
+        self.products = Some(products);
+        Ok(())
+    }
 
-        if response.status().is_success() {
-            // let products: Vec<WooCommerceProduct> = response.json().await?;
-            // self.products = Some(products);
-            // Ok(())
-            let body = response.text().await?;
+    // same as above but reports how far along the download is
+    pub async fn fetch_populate_products_with_progress<F>(
+        &mut self,
+        progress: F,
+    ) -> Result<(), WcError>
+    where
+        F: FnMut(&FetchProgress),
+    {
+        let products = self.fetch_all_products(progress).await?;
+        self.products = Some(products);
+        Ok(())
+    }
 
-            let re = Regex::new(r"<[^>]*>").unwrap();
-            let sanitized_body = re.replace_all(&body, "").to_string();
+    // fetch and populate
+    pub async fn fetch_products_raw(&self) -> Result<Vec<WooCommerceProduct>, WcError> {
+        self.fetch_all_products(|_| {}).await
+    }
 
-            let products: Vec<WooCommerceProduct> = serde_json::from_str(&sanitized_body)?;
+    // walks every page of /products until X-WP-TotalPages is reached (or a page comes
+    // back empty if the store doesn't send the headers). progress is called after each page.
+    pub async fn fetch_all_products<F>(
+        &self,
+        mut progress: F,
+    ) -> Result<Vec<WooCommerceProduct>, WcError>
+    where
+        F: FnMut(&FetchProgress),
+    {
+        let mut products: Vec<WooCommerceProduct> = Vec::new();
+        let mut page: u32 = 1;
 
-            self.products = Some(products);
-            Ok(())
-        } else {
-            let status = response.status();
-            let error_msg = format!("Failed to fetch products: {}", status);
-            Err(error_msg.into())
+        loop {
+            let (mut batch, total, total_pages) = self.fetch_products_page(page).await?;
+            let batch_len = batch.len();
+            products.append(&mut batch);
+
+            progress(&FetchProgress {
+                page,
+                total_pages,
+                fetched: products.len(),
+                total,
+            });
+
+            let done = match total_pages {
+                Some(total_pages) => page >= total_pages,
+                None => batch_len < WC_PER_PAGE as usize,
+            };
+            if done || batch_len == 0 {
+                break;
+            }
+            page += 1;
         }
+
+        Ok(products)
     }
 
-    // fetch and populate
-    pub async fn fetch_products_raw(
+    // fetches a single page, returns (products, X-WP-Total, X-WP-TotalPages)
+    async fn fetch_products_page(
         &self,
-    ) -> Result<Vec<WooCommerceProduct>, Box<dyn std::error::Error>> {
+        page: u32,
+    ) -> Result<(Vec<WooCommerceProduct>, Option<usize>, Option<u32>), WcError> {
         let url = format!(
             "{}/wp-json/wc/v3/products",
             self.base_api.trim_end_matches('/')
         );
-        let client = Client::new();
-
-        // build proper auth header
-        let auth_header = self.build_authorization_header();
 
-        let response = client
-            .get(&url)
-            .header("Authorization", auth_header) // put basicauth header in myself
-            .send()
+        let response = self
+            .send(Idempotency::Idempotent, || {
+                self.client
+                    .get(&url)
+                    .query(&[("per_page", WC_PER_PAGE), ("page", page)])
+            })
             .await?;
 
-        if response.status().is_success() {
-            let body = response.text().await?;
+        let context = format!("fetch products (page {})", page);
+        let response = wc_error::check(response, context.as_str()).await?;
+        let total: Option<usize> = header_number(response.headers(), "X-WP-Total");
+        let total_pages: Option<u32> = header_number(response.headers(), "X-WP-TotalPages");
 
-            // sanitize response body
-            let re = Regex::new(r"<[^>]*>").unwrap();
-            let sanitized_body = re.replace_all(&body, "").to_string();
+        let body = response.text().await?;
 
-            // deserialize json string sanitized
-            let products: Vec<WooCommerceProduct> = serde_json::from_str(&sanitized_body)?;
-            // let products: Vec<WooCommerceProduct> = response.json().await?;
-            Ok(products)
-        } else {
-            let status = response.status();
-            let error_msg = format!("Failed to fetch products: {}", status);
-            Err(error_msg.into())
-        }
+        // descriptions come back as the store has them, HTML and all. html.rs turns them
+        // into text where that's needed.
+        let products: Vec<WooCommerceProduct> = wc_error::parse_json(&body, context)?;
+        Ok((products, total, total_pages))
     }
+
     pub async fn post_product(
         &self,
         product: WooCommerceProduct,
-    ) -> Result<WooCommerceProduct, Box<dyn std::error::Error>> {
+    ) -> Result<WooCommerceProduct, WcError> {
         let url = format!(
             "{}/wp-json/wc/v3/products",
             self.base_api.trim_end_matches('/')
         );
-        let client = Client::new();
+        product.validate()?;
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::IfNotProcessed, || {
+                client.post(&url).json(&product) // serialize
+            })
+            .await?;
 
-        // gotta build auth header
-        let auth_header = self.build_authorization_header();
+        let context = format!("create product {}", product.name);
+        let created_product: WooCommerceProduct = wc_error::read_json(response, context).await?;
+        Ok(created_product)
+    }
 
-        let response = client
-            .post(&url)
-            .header("Authorization", auth_header) // auth!
-            .json(&product) // serialize
-            .send()
+    // PUT /products/{id}. patch only needs the fields that changed.
+    pub async fn update_product(
+        &self,
+        id: u64,
+        patch: &serde_json::Value,
+    ) -> Result<WooCommerceProduct, WcError> {
+        let url = format!(
+            "{}/wp-json/wc/v3/products/{}",
+            self.base_api.trim_end_matches('/'),
+            id
+        );
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::Idempotent, || client.put(&url).json(patch))
             .await?;
 
-        if response.status().is_success() {
-            let created_product: WooCommerceProduct = response.json().await?;
-            Ok(created_product)
-        } else {
-            let status = response.status();
-            let error_msg = format!("Failed to create product: {}", status);
-            Err(error_msg.into())
+        let context = format!("update product {}", id);
+        let updated_product: WooCommerceProduct = wc_error::read_json(response, context).await?;
+        Ok(updated_product)
+    }
+
+    // pushes the patches out of LocalSession::compare_wc_vd_updates through /products/batch
+    pub async fn batch_update_local(
+        &mut self,
+        updates: Vec<ProductUpdate>,
+    ) -> Result<BatchReport, WcError> {
+        let patches: Vec<serde_json::Value> =
+            updates.into_iter().map(|update| update.patch).collect();
+        let patches = self.prepare_patches(patches).await?;
+        self.batch_products(Vec::new(), patches, Vec::new()).await
+    }
+
+    // applies LocalSession::compare_wc_vd_delisted. out of stock and draft are updates,
+    // trash is a batch delete without force (WooCommerce moves it to the trash).
+    pub async fn batch_delist(&self, delistings: Vec<Delisting>) -> Result<BatchReport, WcError> {
+        let mut patches: Vec<serde_json::Value> = Vec::new();
+        let mut delete: Vec<u64> = Vec::new();
+
+        for delisting in delistings {
+            match delisting_patch(&delisting) {
+                Some(patch) => patches.push(patch),
+                None => delete.push(delisting.wc_id),
+            }
+        }
+
+        self.batch_products(Vec::new(), patches, delete).await
+    }
+
+    // carries out a SyncPlan in as few /products/batch requests as possible. building the
+    // plan makes no writes, this is the only step that does.
+    pub async fn apply_plan(&mut self, plan: &SyncPlan) -> Result<BatchReport, WcError> {
+        let (create, rejected) = self.local_to_woocommerce(plan.creates()).await?;
+        let update: Vec<serde_json::Value> = plan
+            .updates()
+            .into_iter()
+            .map(|update| update.patch)
+            .collect();
+        let mut update = self.prepare_patches(update).await?;
+        let mut delete: Vec<u64> = Vec::new();
+
+        for delisting in plan.delistings() {
+            match delisting_patch(&delisting) {
+                Some(patch) => update.push(patch),
+                None => delete.push(delisting.wc_id),
+            }
+        }
+
+        let mut report = self.batch_products(create, update, delete).await?;
+        report.reject(&rejected);
+        Ok(report)
+    }
+
+    // uploads every LocalObject (usually the Vec out of LocalSession::compare_wc_vd) through
+    // /products/batch instead of one POST per product.
+    pub async fn batch_post_local(
+        &mut self,
+        objects: Vec<LocalObject>,
+    ) -> Result<BatchReport, WcError> {
+        let (create, rejected) = self.local_to_woocommerce(objects).await?;
+        let mut report = self.batch_products(create, Vec::new(), Vec::new()).await?;
+        report.reject(&rejected);
+        Ok(report)
+    }
+
+    // LocalObject -> WooCommerceProduct with category names resolved to ids and, if there's
+    // an image cache, images uploaded to the media library. products that fail validation
+    // come back separately so they can be reported with the rest.
+    pub(crate) async fn local_to_woocommerce(
+        &mut self,
+        objects: Vec<LocalObject>,
+    ) -> Result<(Vec<WooCommerceProduct>, Vec<Rejected>), WcError> {
+        let mut products: Vec<WooCommerceProduct> = Vec::new();
+        let mut rejected: Vec<Rejected> = Vec::new();
+        for mut object in objects {
+            let mut product = object.to_woocommerce_object();
+            // one bad row shouldn't sink the whole batch
+            if let Err(error) = product.validate() {
+                rejected.push(Rejected { product, error });
+                continue;
+            }
+            self.resolve_product_categories(&mut product).await?;
+            self.resolve_product_attributes(&mut product.attributes)
+                .await?;
+            self.resolve_product_tags(&mut product.tags).await?;
+            products.push(product);
+        }
+
+        if let Some(mut cache) = self.image_cache.take() {
+            for product in products.iter_mut() {
+                self.attach_media(&mut cache, &mut product.images).await;
+            }
+            self.image_cache = Some(cache);
         }
+        Ok((products, rejected))
     }
+
+    // update patches get the same treatment as creates: global attribute ids, tag ids and,
+    // with an image cache, media ids
+    async fn prepare_patches(
+        &mut self,
+        mut patches: Vec<serde_json::Value>,
+    ) -> Result<Vec<serde_json::Value>, WcError> {
+        for patch in patches.iter_mut() {
+            self.resolve_patch_attributes(patch).await?;
+            self.resolve_patch_tags(patch).await?;
+        }
+        if let Some(mut cache) = self.image_cache.take() {
+            let mut result = Ok(());
+            for patch in patches.iter_mut() {
+                result = self.attach_media_to_patch(&mut cache, patch).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            self.image_cache = Some(cache);
+            result?;
+        }
+        Ok(patches)
+    }
+
+    // create/update/delete through /products/batch, split into requests of at most
+    // WC_BATCH_LIMIT items. update entries are JSON objects holding the product "id" and
+    // whatever fields should change. a failed item doesn't fail the whole call, it shows
+    // up in the report with WooCommerce's error code and message.
+    pub async fn batch_products(
+        &self,
+        create: Vec<WooCommerceProduct>,
+        update: Vec<serde_json::Value>,
+        delete: Vec<u64>,
+    ) -> Result<BatchReport, WcError> {
+        let url = format!(
+            "{}/wp-json/wc/v3/products/batch",
+            self.base_api.trim_end_matches('/')
+        );
+        let client = &self.client;
+        let mut report = BatchReport::default();
+
+        for request in BatchRequest::chunked(create, update, delete) {
+            // updates and deletes land the same way twice, creates would duplicate
+            let idempotency = if request.create.is_empty() {
+                Idempotency::Idempotent
+            } else {
+                Idempotency::IfNotProcessed
+            };
+
+            let response = self
+                .send(idempotency, || client.post(&url).json(&request))
+                .await?;
+
+            let context = format!(
+                "batch products ({} items already processed)",
+                report.results.len()
+            );
+            let batch_response: BatchResponse = wc_error::read_json(response, context).await?;
+            report.absorb(&request, batch_response);
+        }
+
+        Ok(report)
+    }
+
     pub fn get_length(&self) -> i32 {
         let length = self.products.as_ref().unwrap().len();
         length as i32
     }
 }
 
+// body of a POST /products/batch
+#[derive(Debug, Serialize, Default, Clone)]
+pub struct BatchRequest {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub create: Vec<WooCommerceProduct>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub update: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub delete: Vec<u64>,
+}
+
+impl BatchRequest {
+    pub fn len(&self) -> usize {
+        self.create.len() + self.update.len() + self.delete.len()
+    }
+
+    // packs everything into as few requests as possible, WC_BATCH_LIMIT items per request
+    pub fn chunked(
+        create: Vec<WooCommerceProduct>,
+        update: Vec<serde_json::Value>,
+        delete: Vec<u64>,
+    ) -> Vec<BatchRequest> {
+        let mut requests: Vec<BatchRequest> = Vec::new();
+        let mut current = BatchRequest::default();
+
+        for product in create {
+            current.create.push(product);
+            if current.len() == WC_BATCH_LIMIT {
+                requests.push(std::mem::take(&mut current));
+            }
+        }
+        for patch in update {
+            current.update.push(patch);
+            if current.len() == WC_BATCH_LIMIT {
+                requests.push(std::mem::take(&mut current));
+            }
+        }
+        for id in delete {
+            current.delete.push(id);
+            if current.len() == WC_BATCH_LIMIT {
+                requests.push(std::mem::take(&mut current));
+            }
+        }
+        if current.len() > 0 {
+            requests.push(current);
+        }
+
+        requests
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BatchResponse {
+    #[serde(default)]
+    pub create: Vec<BatchResponseItem>,
+    #[serde(default)]
+    pub update: Vec<BatchResponseItem>,
+    #[serde(default)]
+    pub delete: Vec<BatchResponseItem>,
+}
+
+// either the saved product or {id, error: {code, message, data}}, so only the bits we need
+#[derive(Debug, Deserialize, Clone)]
+pub struct BatchResponseItem {
+    #[serde(default)]
+    pub id: u64,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub sku: Option<String>,
+    #[serde(default)]
+    pub error: Option<WcApiError>,
+}
+
+// WooCommerce's error body, {code, message, data}
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WcApiError {
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOp {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchItemResult {
+    pub op: BatchOp,
+    pub id: Option<u64>,
+    pub sku: String,
+    pub name: String,
+    pub error: Option<WcApiError>,
+}
+
+impl BatchItemResult {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// a product local_to_woocommerce held back, and why
+#[derive(Debug)]
+pub struct Rejected {
+    pub product: WooCommerceProduct,
+    pub error: WcError,
+}
+
+impl Rejected {
+    pub fn to_batch_item(&self) -> BatchItemResult {
+        BatchItemResult {
+            op: BatchOp::Create,
+            id: None,
+            sku: self.product.sku.clone(),
+            name: self.product.name.clone(),
+            error: Some(WcApiError {
+                code: String::from("hcrelay_invalid_product"),
+                message: self.error.to_string(),
+                data: None,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    pub results: Vec<BatchItemResult>,
+}
+
+impl BatchReport {
+    // pairs each response item with what we sent, WooCommerce keeps the order
+    fn absorb(&mut self, request: &BatchRequest, response: BatchResponse) {
+        for (idx, item) in response.create.into_iter().enumerate() {
+            let sent = request.create.get(idx);
+            self.results.push(BatchItemResult {
+                op: BatchOp::Create,
+                id: if item.id == 0 { None } else { Some(item.id) },
+                sku: item.sku.or(sent.map(|p| p.sku.clone())).unwrap_or_default(),
+                name: item
+                    .name
+                    .or(sent.map(|p| p.name.clone()))
+                    .unwrap_or_default(),
+                error: item.error,
+            });
+        }
+        for (idx, item) in response.update.into_iter().enumerate() {
+            let sent = request.update.get(idx);
+            let sent_str = |key: &str| {
+                sent.and_then(|patch| patch.get(key))
+                    .and_then(|value| value.as_str())
+                    .map(|value| value.to_owned())
+            };
+            let id = if item.id == 0 {
+                sent.and_then(|patch| patch.get("id"))
+                    .and_then(|id| id.as_u64())
+            } else {
+                Some(item.id)
+            };
+            self.results.push(BatchItemResult {
+                op: BatchOp::Update,
+                id,
+                sku: item.sku.or(sent_str("sku")).unwrap_or_default(),
+                name: item.name.or(sent_str("name")).unwrap_or_default(),
+                error: item.error,
+            });
+        }
+        for (idx, item) in response.delete.into_iter().enumerate() {
+            let id = if item.id == 0 {
+                request.delete.get(idx).copied()
+            } else {
+                Some(item.id)
+            };
+            self.results.push(BatchItemResult {
+                op: BatchOp::Delete,
+                id,
+                sku: item.sku.unwrap_or_default(),
+                name: item.name.unwrap_or_default(),
+                error: item.error,
+            });
+        }
+    }
+
+    // products that never went out because they failed validation, as failed creates
+    pub fn reject(&mut self, rejected: &[Rejected]) {
+        self.results
+            .extend(rejected.iter().map(|rejected| rejected.to_batch_item()));
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.is_ok()).count()
+    }
+
+    pub fn failed(&self) -> Vec<&BatchItemResult> {
+        self.results.iter().filter(|r| !r.is_ok()).collect()
+    }
+
+    pub fn debug(&self) -> String {
+        let mut str = format!(
+            "--- BATCH REPORT ---\n{} ok, {} failed\n",
+            self.succeeded(),
+            self.failed().len()
+        );
+        for result in self.failed() {
+            let error = result.error.as_ref().unwrap();
+            str.push_str(&format!(
+                "{:?} FAILED: {} (SKU: {}) -> {}: {}\n",
+                result.op, result.name, result.sku, error.code, error.message
+            ));
+        }
+        str
+    }
+}
+
+// the update patch for a delisting, None when it's a delete (trash) instead
+fn delisting_patch(delisting: &Delisting) -> Option<serde_json::Value> {
+    match delisting.action {
+        DelistAction::OutOfStock => Some(serde_json::json!({
+            "id": delisting.wc_id,
+            "manage_stock": true,
+            "stock_quantity": 0,
+            "stock_status": "outofstock",
+        })),
+        DelistAction::Draft => Some(serde_json::json!({
+            "id": delisting.wc_id,
+            "status": "draft",
+        })),
+        DelistAction::Trash => None,
+    }
+}
+
+// the client every request to the store goes through. idle connections are kept around
+// between calls, the keep-alive is what makes concurrent uploads cheap.
+fn http_client() -> Client {
+    Client::builder()
+        .user_agent(concat!("hcrelay/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(30))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(60))
+        .build()
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "[] couldn't build the HTTP client, using the defaults: {}",
+                e
+            );
+            Client::new()
+        })
+}
+
+// WooCommerce returns term names HTML-escaped ("Tops &amp; Tees")
+pub(crate) fn decode_entities(name: &str) -> String {
+    name.replace("&amp;", "&")
+        .replace("&#039;", "'")
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+}
+
+// how tag and attribute term names are compared, "Like  New " and "like new" are the same
+// term and so are "Tops &amp; Tees" and "tops & tees"
+pub fn normalize_term(name: &str) -> String {
+    decode_entities(name)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn header_number<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<T>().ok())
+}
+
 impl WooCommerceProduct {
+    // what WooCommerce would reject (or silently mangle) anyway
+    pub fn validate(&self) -> Result<(), WcError> {
+        let label = if self.sku.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} (SKU: {})", self.name, self.sku)
+        };
+        if self.name.trim().is_empty() {
+            return Err(WcError::validation(format!(
+                "product {} has no name",
+                label.trim()
+            )));
+        }
+        if !WC_STATUSES.contains(&self.status.as_str()) {
+            return Err(WcError::validation(format!(
+                "{} has status {:?}, WooCommerce wants one of {}",
+                label,
+                self.status,
+                WC_STATUSES.join(", ")
+            )));
+        }
+        if let Some(stock_status) = &self.stock_status {
+            if !WC_STOCK_STATUSES.contains(&stock_status.as_str()) {
+                return Err(WcError::validation(format!(
+                    "{} has stock status {:?}, WooCommerce wants one of {}",
+                    label,
+                    stock_status,
+                    WC_STOCK_STATUSES.join(", ")
+                )));
+            }
+        }
+        if let (Some(regular), Some(sale)) = (self.regular_price, self.sale_price) {
+            if sale >= regular {
+                return Err(WcError::validation(format!(
+                    "{} has sale price {} not below its regular price {}",
+                    label, sale, regular
+                )));
+            }
+        }
+        Ok(())
+    }
+
     pub fn debug(&self) -> String {
         // dbg single WC product
         let mut categories_str = String::new();
@@ -192,6 +880,7 @@ impl WooCommerceProduct {
 
         format!(
             "--- WOOCOMMERCE PRODUCT ---
+ID: {}
 NAME: {}
 DESC: {}
 PRICE: {}
@@ -200,9 +889,14 @@ IMAGES URL: {}
 STOCK_QTTY: {}
 STATUS: {}
 SERIAL: {}",
+            self.id
+                .map(|id| id.to_string())
+                .unwrap_or(String::from("N/A")),
             self.name,
-            self.description,
-            self.regular_price,
+            html::to_text(&self.description),
+            self.regular_price
+                .map(|price| price.to_string())
+                .unwrap_or_default(),
             categories_str,
             images_str,
             stock_qty,
@@ -211,3 +905,215 @@ SERIAL: {}",
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const PRODUCTS: &str = "/wp-json/wc/v3/products";
+
+    fn products(from: u64, n: u64) -> serde_json::Value {
+        (from..from + n)
+            .map(|id| {
+                serde_json::json!({
+                    "id": id,
+                    "name": format!("Product {}", id),
+                    "regular_price": "10.00",
+                    "description": "",
+                    "categories": [],
+                    "images": [],
+                    "stock_quantity": null,
+                    "status": "publish",
+                    "sku": format!("SKU-{}", id),
+                })
+            })
+            .collect()
+    }
+
+    async fn page(server: &MockServer, page: u32, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path(PRODUCTS))
+            .and(query_param("per_page", WC_PER_PAGE.to_string()))
+            .and(query_param("page", page.to_string()))
+            .respond_with(response)
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    fn store(server: &MockServer) -> ObjWooCommerce {
+        ObjWooCommerce::new_with_auth(server.uri(), String::from("ck"), String::from("cs"))
+    }
+
+    #[tokio::test]
+    async fn pages_until_total_pages() {
+        let server = MockServer::start().await;
+        for (n, (from, len)) in [(1, 2), (3, 2), (5, 1)].into_iter().enumerate() {
+            page(
+                &server,
+                n as u32 + 1,
+                ResponseTemplate::new(200)
+                    .insert_header("X-WP-Total", "5")
+                    .insert_header("X-WP-TotalPages", "3")
+                    .set_body_json(products(from, len)),
+            )
+            .await;
+        }
+
+        let mut seen: Vec<String> = Vec::new();
+        let fetched = store(&server)
+            .fetch_all_products(|progress| seen.push(progress.debug()))
+            .await
+            .unwrap();
+
+        let ids: Vec<Option<u64>> = fetched.iter().map(|product| product.id).collect();
+        assert_eq!(ids, (1..=5).map(Some).collect::<Vec<Option<u64>>>());
+        assert_eq!(
+            seen,
+            vec![
+                "page 1 of 3, 2 of 5 products fetched",
+                "page 2 of 3, 4 of 5 products fetched",
+                "page 3 of 3, 5 of 5 products fetched",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn without_headers_a_short_page_is_the_last() {
+        let server = MockServer::start().await;
+        page(
+            &server,
+            1,
+            ResponseTemplate::new(200).set_body_json(products(1, WC_PER_PAGE as u64)),
+        )
+        .await;
+        page(
+            &server,
+            2,
+            ResponseTemplate::new(200).set_body_json(products(101, 3)),
+        )
+        .await;
+
+        let mut seen: Vec<String> = Vec::new();
+        let fetched = store(&server)
+            .fetch_all_products(|progress| seen.push(progress.debug()))
+            .await
+            .unwrap();
+
+        assert_eq!(fetched.len(), WC_PER_PAGE as usize + 3);
+        assert_eq!(seen[1], "page 2 of ?, 103 of ? products fetched");
+    }
+
+    fn product(id: u64) -> WooCommerceProduct {
+        serde_json::from_value(products(id, 1)[0].clone()).unwrap()
+    }
+
+    #[test]
+    fn batches_split_at_the_limit() {
+        let create: Vec<WooCommerceProduct> = (1..=150).map(product).collect();
+        let update: Vec<serde_json::Value> = (1..=60)
+            .map(|id| serde_json::json!({"id": id, "regular_price": "12.00"}))
+            .collect();
+        let delete: Vec<u64> = (1..=5).collect();
+
+        let requests = BatchRequest::chunked(create, update, delete);
+        let sizes: Vec<(usize, usize, usize)> = requests
+            .iter()
+            .map(|request| {
+                (
+                    request.create.len(),
+                    request.update.len(),
+                    request.delete.len(),
+                )
+            })
+            .collect();
+        assert_eq!(sizes, vec![(100, 0, 0), (50, 50, 0), (0, 10, 5)]);
+        assert!(requests
+            .iter()
+            .all(|request| request.len() <= WC_BATCH_LIMIT));
+        assert!(BatchRequest::chunked(Vec::new(), Vec::new(), Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_chunk_goes_out() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let create: Vec<WooCommerceProduct> = (1..=101).map(product).collect();
+        store(&server)
+            .batch_products(create, Vec::new(), Vec::new())
+            .await
+            .unwrap();
+
+        let sizes: Vec<usize> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                body["create"].as_array().unwrap().len()
+            })
+            .collect();
+        assert_eq!(sizes, vec![100, 1]);
+    }
+
+    #[tokio::test]
+    async fn failed_items_keep_their_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "create": [
+                    {"id": 21, "name": "Product 1", "sku": "SKU-1"},
+                    {"id": 0, "error": {
+                        "code": "product_invalid_sku",
+                        "message": "Invalid or duplicated SKU.",
+                        "data": {"status": 400},
+                    }},
+                ],
+                "update": [{"id": 0, "error": {
+                    "code": "woocommerce_rest_product_invalid_id",
+                    "message": "Invalid ID.",
+                }}],
+                "delete": [{"id": 9, "name": "Gone"}],
+            })))
+            .mount(&server)
+            .await;
+
+        let report = store(&server)
+            .batch_products(
+                vec![product(1), product(2)],
+                vec![serde_json::json!({"id": 7, "name": "Renamed", "sku": "SKU-7"})],
+                vec![9],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(report.succeeded(), 2);
+        let failed = report.failed();
+        assert_eq!(failed.len(), 2);
+        // what WooCommerce left out of an error item comes from what we sent
+        assert_eq!(failed[0].op, BatchOp::Create);
+        assert_eq!((failed[0].id, failed[0].sku.as_str()), (None, "SKU-2"));
+        assert_eq!(failed[0].name, "Product 2");
+        assert_eq!(
+            failed[0].error.as_ref().unwrap().code,
+            "product_invalid_sku"
+        );
+        assert_eq!(failed[1].op, BatchOp::Update);
+        assert_eq!((failed[1].id, failed[1].sku.as_str()), (Some(7), "SKU-7"));
+        assert_eq!(report.results[0].id, Some(21));
+        assert_eq!(report.results[3].op, BatchOp::Delete);
+        assert_eq!(report.results[3].id, Some(9));
+        assert!(report.debug().contains("Invalid or duplicated SKU."));
+    }
+}
@@ -0,0 +1,423 @@
+use serde::{Serialize, Serializer};
+
+use crate::{
+    local::{DelistPolicy, DelistReason, Delisting, LocalObject, LocalSession, ProductUpdate},
+    matching::AmbiguousMatch,
+    money::Money,
+    pricing::PriceTrace,
+};
+
+// what a sync would do to one product
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlanAction {
+    Create {
+        #[serde(rename = "payload", serialize_with = "serialize_payload")]
+        object: Box<LocalObject>,
+    },
+    Update(ProductUpdate),
+    Unpublish(Delisting),
+    Skip,
+}
+
+impl PlanAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PlanAction::Create { .. } => "CREATE",
+            PlanAction::Update(_) => "UPDATE",
+            PlanAction::Unpublish(_) => "UNPUBLISH",
+            PlanAction::Skip => "SKIP",
+        }
+    }
+}
+
+// the parts of a create worth reviewing in the JSON, as they'd go up. the whole product
+// would bury them under descriptions and attributes.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatePayload {
+    pub regular_price: Option<Money>,
+    pub sale_price: Option<Money>,
+    pub status: String,
+    pub stock_status: Option<String>,
+    pub images: Vec<String>,
+}
+
+impl CreatePayload {
+    pub fn of(object: &LocalObject) -> Self {
+        let product = object.clone().to_woocommerce_object();
+        Self {
+            regular_price: product.regular_price,
+            sale_price: product.sale_price,
+            status: product.status,
+            stock_status: product.stock_status,
+            images: product.images.into_iter().map(|image| image.src).collect(),
+        }
+    }
+}
+
+fn serialize_payload<S: Serializer>(
+    object: &LocalObject,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    CreatePayload::of(object).serialize(serializer)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanEntry {
+    pub name: String,
+    pub sku: String,
+    pub wc_id: Option<u64>,
+    pub action: PlanAction,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PriceTrace>, // how PriceRules got from the vendoo price to ours
+}
+
+// everything a sync would do, built without any HTTP writes. review it with to_table or
+// to_json, then hand it to ObjWooCommerce::apply_plan.
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct SyncPlan {
+    pub entries: Vec<PlanEntry>,
+}
+
+impl SyncPlan {
+    pub fn build(session: &LocalSession, policy: DelistPolicy) -> Self {
+        let mut entries: Vec<PlanEntry> = Vec::new();
+        let report = session.match_wc_vd();
+
+        for product_match in &report.matches {
+            let vp_object = &session.local_vp[product_match.vp_idx];
+            let wp_object = &session.local_wp[product_match.wp_idx];
+            let mut entry = PlanEntry {
+                name: vp_object.name.clone(),
+                sku: vp_object.sku.clone(),
+                wc_id: wp_object.wc_id,
+                action: PlanAction::Skip,
+                reason: String::new(),
+                pricing: vp_object.pricing.clone(),
+            };
+
+            let wc_id = match wp_object.wc_id {
+                Some(wc_id) => wc_id,
+                None => {
+                    entry.reason = String::from("matched product has no WooCommerce id");
+                    entries.push(entry);
+                    continue;
+                }
+            };
+
+            if vp_object.status == "Active" {
+                match vp_object.diff_against(wp_object, wc_id) {
+                    Some(update) => {
+                        let fields: Vec<&str> =
+                            update.changed.iter().map(|change| change.field).collect();
+                        entry.reason = format!(
+                            "matched by {:?}, changed: {}",
+                            product_match.key,
+                            fields.join(", ")
+                        );
+                        entry.action = PlanAction::Update(update);
+                    }
+                    None => {
+                        entry.reason = format!("matched by {:?}, up to date", product_match.key);
+                    }
+                }
+            } else if let Some(reason) = vp_object.delist_reason() {
+                if wp_object.is_delisted_as(policy.action) {
+                    entry.reason = format!("{}, already {:?}", reason.debug(), policy.action);
+                } else {
+                    entry.reason = reason.debug();
+                    entry.action = PlanAction::Unpublish(Delisting {
+                        wc_id,
+                        name: wp_object.name.clone(),
+                        sku: wp_object.sku.clone(),
+                        reason,
+                        action: policy.action,
+                    });
+                }
+            } else {
+                entry.reason = format!("vendoo status {}", vp_object.status);
+            }
+
+            entries.push(entry);
+        }
+
+        for vp_idx in &report.unmatched_vp {
+            let vp_object = &session.local_vp[*vp_idx];
+            let (action, reason) = if vp_object.status == "Active" {
+                (
+                    PlanAction::Create {
+                        object: Box::new(vp_object.clone()),
+                    },
+                    String::from("not in WooCommerce yet"),
+                )
+            } else {
+                (
+                    PlanAction::Skip,
+                    format!("not in WooCommerce, vendoo status {}", vp_object.status),
+                )
+            };
+            entries.push(PlanEntry {
+                name: vp_object.name.clone(),
+                sku: vp_object.sku.clone(),
+                wc_id: None,
+                action,
+                reason,
+                pricing: vp_object.pricing.clone(),
+            });
+        }
+
+        for wp_idx in &report.unmatched_wp {
+            let wp_object = &session.local_wp[*wp_idx];
+            let mut entry = PlanEntry {
+                name: wp_object.name.clone(),
+                sku: wp_object.sku.clone(),
+                wc_id: wp_object.wc_id,
+                action: PlanAction::Skip,
+                reason: format!("not in the vendoo CSV, {}", wp_object.status_report()),
+                pricing: None,
+            };
+            if policy.include_missing {
+                if let Some(wc_id) = wp_object.wc_id {
+                    if !wp_object.is_delisted_as(policy.action) {
+                        entry.action = PlanAction::Unpublish(Delisting {
+                            wc_id,
+                            name: wp_object.name.clone(),
+                            sku: wp_object.sku.clone(),
+                            reason: DelistReason::Missing,
+                            action: policy.action,
+                        });
+                    }
+                }
+            }
+            entries.push(entry);
+        }
+
+        for ambiguous in &report.ambiguous {
+            let reason = format!(
+                "ambiguous: {}",
+                ambiguous.debug(&session.local_vp, &session.local_wp)
+            );
+            let (name, sku, wc_id) = match ambiguous {
+                AmbiguousMatch::DuplicateVendooSku {
+                    sku,
+                    vp_idx,
+                    wp_idx,
+                } => {
+                    // the store product it would have matched, when there's just the one
+                    let wc_id = match wp_idx[..] {
+                        [idx] => session.local_wp[idx].wc_id,
+                        _ => None,
+                    };
+                    (session.local_vp[vp_idx[0]].name.clone(), sku.clone(), wc_id)
+                }
+                AmbiguousMatch::DuplicateWooCommerceSku { sku, wp_idx } => {
+                    (session.local_wp[wp_idx[0]].name.clone(), sku.clone(), None)
+                }
+                AmbiguousMatch::AmbiguousTitle { vp_idx, .. } => {
+                    let vp_object = &session.local_vp[*vp_idx];
+                    (vp_object.name.clone(), vp_object.sku.clone(), None)
+                }
+                AmbiguousMatch::AlreadyClaimed { vp_idx, wp_idx, .. } => {
+                    let vp_object = &session.local_vp[*vp_idx];
+                    (
+                        vp_object.name.clone(),
+                        vp_object.sku.clone(),
+                        session.local_wp[*wp_idx].wc_id,
+                    )
+                }
+            };
+            entries.push(PlanEntry {
+                name,
+                sku,
+                wc_id,
+                action: PlanAction::Skip,
+                reason,
+                pricing: None,
+            });
+        }
+
+        Self { entries }
+    }
+
+    pub fn creates(&self) -> Vec<LocalObject> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match &entry.action {
+                PlanAction::Create { object } => Some(object.as_ref().clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn updates(&self) -> Vec<ProductUpdate> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match &entry.action {
+                PlanAction::Update(update) => Some(update.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn delistings(&self) -> Vec<Delisting> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match &entry.action {
+                PlanAction::Unpublish(delisting) => Some(delisting.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // true if applying the plan wouldn't touch the store
+    pub fn is_noop(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|entry| matches!(entry.action, PlanAction::Skip))
+    }
+
+    pub fn summary(&self) -> String {
+        let count = |label: &str| {
+            self.entries
+                .iter()
+                .filter(|entry| entry.action.label() == label)
+                .count()
+        };
+        format!(
+            "{} create, {} update, {} unpublish, {} skip",
+            count("CREATE"),
+            count("UPDATE"),
+            count("UNPUBLISH"),
+            count("SKIP")
+        )
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // one row per product, updates get their field diff underneath
+    pub fn to_table(&self) -> String {
+        let rows: Vec<[String; 5]> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                [
+                    entry.action.label().to_owned(),
+                    entry
+                        .wc_id
+                        .map(|id| id.to_string())
+                        .unwrap_or(String::from("-")),
+                    truncate(&entry.sku, 16),
+                    truncate(&entry.name, 40),
+                    entry.reason.clone(),
+                ]
+            })
+            .collect();
+
+        let headers = ["ACTION", "WC ID", "SKU", "NAME", "REASON"];
+        let mut widths: [usize; 4] = [0; 4];
+        for (idx, width) in widths.iter_mut().enumerate() {
+            *width = rows
+                .iter()
+                .map(|row| row[idx].chars().count())
+                .chain(std::iter::once(headers[idx].len()))
+                .max()
+                .unwrap_or(0);
+        }
+
+        let mut str = format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {}\n",
+            headers[0],
+            headers[1],
+            headers[2],
+            headers[3],
+            headers[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3]
+        );
+        for (row, entry) in rows.iter().zip(&self.entries) {
+            str.push_str(&format!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {}\n",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            ));
+            if let (PlanAction::Create { .. } | PlanAction::Update(_), Some(pricing)) =
+                (&entry.action, &entry.pricing)
+            {
+                str.push_str(&format!("    price: {}\n", pricing.debug()));
+            }
+            if let PlanAction::Update(update) = &entry.action {
+                for change in &update.changed {
+                    str.push_str(&format!(
+                        "    {}: {} -> {}\n",
+                        change.field,
+                        truncate(&change.wc, 60),
+                        truncate(&change.vd, 60)
+                    ));
+                }
+            }
+        }
+        str.push_str(&format!("--- {} ---\n", self.summary()));
+        str
+    }
+}
+
+fn truncate(str: &str, max: usize) -> String {
+    let str = str.replace('\n', " ");
+    if str.chars().count() <= max {
+        str
+    } else {
+        let mut cut: String = str.chars().take(max.saturating_sub(3)).collect();
+        cut.push_str("...");
+        cut
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::local::DelistAction;
+    use crate::obj_vd::VendooProduct;
+
+    #[test]
+    fn creates_show_what_would_go_up() {
+        let product: VendooProduct = serde_json::from_value(serde_json::json!({
+            "Sku": "A-1",
+            "Title": "Denim jacket",
+            "Price": "$19.9",
+            "Status": "Active",
+            "Images": "https://cdn.x/a.jpg, https://cdn.x/b.jpg",
+        }))
+        .unwrap();
+        let session =
+            LocalSession::from_local_products(vec![LocalObject::from_vendoo_object(&product)]);
+        let policy = DelistPolicy {
+            action: DelistAction::OutOfStock,
+            include_missing: false,
+        };
+        let plan = SyncPlan::build(&session, policy);
+        assert_eq!(plan.creates().len(), 1);
+
+        let json: serde_json::Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+        let action = &json["entries"][0]["action"];
+        assert_eq!(action["type"], "create");
+        assert_eq!(action["payload"]["regular_price"], "19.90");
+        assert_eq!(action["payload"]["status"], "publish");
+        assert_eq!(
+            action["payload"]["images"],
+            serde_json::json!(["https://cdn.x/a.jpg", "https://cdn.x/b.jpg"])
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    category_map::{normalize_path, wildcard_match},
+    local::LocalObject,
+    money::Money,
+};
+
+// "-10%", "+15%", "-2", "+2.50". percentages scale the price, plain numbers add to it.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Adjustment {
+    Percent(Decimal),
+    Fixed(Money),
+}
+
+impl TryFrom<String> for Adjustment {
+    type Error = String;
+
+    fn try_from(str: String) -> Result<Self, Self::Error> {
+        let trimmed = str.trim().replace(['$', ' '], "");
+        let (number, percent) = match trimmed.strip_suffix('%') {
+            Some(number) => (number, true),
+            None => (trimmed.as_str(), false),
+        };
+        let value: Decimal = number
+            .trim_start_matches('+')
+            .parse()
+            .map_err(|_| format!("not a price adjustment: {:?}", str))?;
+        Ok(if percent {
+            Adjustment::Percent(value)
+        } else {
+            Adjustment::Fixed(Money::from(value))
+        })
+    }
+}
+
+impl Adjustment {
+    pub fn apply(&self, price: Money) -> Money {
+        match self {
+            Adjustment::Percent(percent) => price.scaled(*percent),
+            Adjustment::Fixed(amount) => (price + *amount).rounded(),
+        }
+    }
+
+    pub fn debug(&self) -> String {
+        match self {
+            Adjustment::Percent(percent) if percent.is_sign_negative() => format!("{}%", percent),
+            Adjustment::Percent(percent) => format!("+{}%", percent),
+            Adjustment::Fixed(amount) if amount.decimal().is_sign_negative() => amount.to_string(),
+            Adjustment::Fixed(amount) => format!("+{}", amount),
+        }
+    }
+}
+
+// every field is optional so a category rule only overrides what it mentions
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PriceRule {
+    pub adjust: Option<Adjustment>,     // applied to the vendoo price
+    pub charm: Option<bool>,            // round to the nearest x.99
+    pub min_margin: Option<Adjustment>, // floor = cost_of_goods adjusted by this
+    pub sale: Option<Adjustment>,       // sale_price = regular price adjusted by this
+}
+
+impl PriceRule {
+    fn merge(&self, over: &PriceRule) -> PriceRule {
+        PriceRule {
+            adjust: over.adjust.or(self.adjust),
+            charm: over.charm.or(self.charm),
+            min_margin: over.min_margin.or(self.min_margin),
+            sale: over.sale.or(self.sale),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CategoryPriceRule {
+    #[serde(rename = "match")]
+    pub pattern: String, // same syntax as the CATEGORY_MAP vendoo column
+    #[serde(flatten)]
+    pub rule: PriceRule,
+}
+
+// PRICE_RULES in .env, a JSON file. the top level is the default rule, categories override
+// it field by field for products in a matching vendoo category (most specific match wins):
+//
+//     {
+//         "adjust": "-10%",
+//         "charm": true,
+//         "min_margin": "25%",
+//         "categories": [
+//             { "match": "Men > Shoes > *", "adjust": "-5%" },
+//             { "match": "Home > *", "charm": false, "sale": "-15%" }
+//         ]
+//     }
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PriceRules {
+    #[serde(flatten)]
+    pub default: PriceRule,
+    #[serde(default)]
+    pub categories: Vec<CategoryPriceRule>,
+}
+
+// how one vendoo price became the WooCommerce one, shown in the sync plan
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PriceTrace {
+    pub vendoo: Money,
+    pub regular: Money,
+    #[serde(default)]
+    pub sale: Option<Money>,
+    pub steps: Vec<String>,
+}
+
+impl PriceTrace {
+    pub fn debug(&self) -> String {
+        let mut str = format!("{} -> {}", self.vendoo, self.regular);
+        if let Some(sale) = &self.sale {
+            str.push_str(&format!(", sale {}", sale));
+        }
+        if !self.steps.is_empty() {
+            str.push_str(&format!(" ({})", self.steps.join(", ")));
+        }
+        str
+    }
+}
+
+impl PriceRules {
+    pub fn from_json(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let str = std::fs::read_to_string(path)?;
+        let rules: PriceRules = serde_json::from_str(&str)?;
+        Ok(rules)
+    }
+
+    // PRICE_RULES if it's set, no rules (vendoo prices go through untouched) if it isn't
+    pub fn from_env() -> Self {
+        match std::env::var("PRICE_RULES") {
+            Ok(path) => match Self::from_json(&path) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    eprintln!("[] couldn't read PRICE_RULES {}: {}", path, e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    // the default rule with the most specific matching category rule laid over it
+    pub fn rule_for(&self, categories: &str) -> PriceRule {
+        let mut best: Option<(&CategoryPriceRule, usize)> = None;
+
+        for category in categories.split(',').map(normalize_path) {
+            if category.is_empty() {
+                continue;
+            }
+            for category_rule in &self.categories {
+                let pattern = normalize_path(&category_rule.pattern);
+                // an exact match beats any wildcard
+                let specificity = if pattern == category {
+                    usize::MAX
+                } else if pattern.contains('*') && wildcard_match(&pattern, &category) {
+                    pattern.chars().filter(|c| *c != '*').count()
+                } else {
+                    continue;
+                };
+                if best.is_none_or(|(_, best)| specificity > best) {
+                    best = Some((category_rule, specificity));
+                }
+            }
+        }
+
+        match best {
+            Some((category_rule, _)) => self.default.merge(&category_rule.rule),
+            None => self.default.clone(),
+        }
+    }
+
+    // rewrites regular_price/sale_price on vendoo objects and records how it got there.
+    // rules match on the vendoo categories, so run this before CategoryMap::apply.
+    pub fn apply(&self, objects: &mut [LocalObject]) -> usize {
+        let mut changed: usize = 0;
+        for object in objects.iter_mut() {
+            // already priced, going again would compound the adjustment
+            if object.pricing.is_some() {
+                continue;
+            }
+            let rule = self.rule_for(&object.categories);
+            let price = match object.regular_price {
+                Some(price) => price,
+                None => continue,
+            };
+            if let Some(trace) = price_with(&rule, price, object.cost_of_goods) {
+                if trace.regular != trace.vendoo || trace.sale.is_some() {
+                    changed += 1;
+                }
+                object.regular_price = Some(trace.regular);
+                object.sale_price = trace.sale;
+                object.pricing = Some(trace);
+            }
+        }
+        changed
+    }
+}
+
+// None if there's nothing to do
+pub fn price_with(
+    rule: &PriceRule,
+    price: Money,
+    cost_of_goods: Option<Money>,
+) -> Option<PriceTrace> {
+    let vendoo = price.rounded();
+    let charm = rule.charm.unwrap_or(false);
+    let mut steps: Vec<String> = Vec::new();
+    let mut regular = vendoo;
+
+    if let Some(adjust) = rule.adjust {
+        regular = adjust.apply(regular);
+        steps.push(adjust.debug());
+    }
+    if charm {
+        let charmed = charm_nearest(regular);
+        if charmed != regular {
+            regular = charmed;
+            steps.push(String::from("charm"));
+        }
+    }
+
+    let floor = match (cost_of_goods, rule.min_margin) {
+        (Some(cost), Some(margin)) if cost.is_positive() => Some(margin.apply(cost)),
+        _ => None,
+    };
+    if let Some(floor) = floor {
+        if regular < floor {
+            regular = if charm { charm_up(floor) } else { floor };
+            steps.push(format!("floor {}", floor));
+        }
+    }
+
+    let mut sale: Option<Money> = None;
+    if let Some(adjust) = rule.sale {
+        let mut sale_price = adjust.apply(regular);
+        if charm {
+            sale_price = charm_nearest(sale_price);
+            if sale_price >= regular {
+                sale_price = sale_price - Money::from_cents(100);
+            }
+        }
+        if sale_price >= regular || !sale_price.is_positive() {
+            steps.push(String::from("no sale, not below regular"));
+        } else if floor.is_some_and(|floor| sale_price < floor) {
+            steps.push(String::from("no sale, below floor"));
+        } else {
+            sale = Some(sale_price);
+            steps.push(format!("sale {}", adjust.debug()));
+        }
+    }
+
+    if steps.is_empty() {
+        return None;
+    }
+
+    Some(PriceTrace {
+        vendoo,
+        regular: regular.max(Money::ZERO),
+        sale,
+        steps,
+    })
+}
+
+// 17.20 -> 16.99, 17.60 -> 17.99. anything under a dollar is left alone.
+fn charm_nearest(price: Money) -> Money {
+    let cents = price.cents();
+    let dollars = (cents + 50) / 100;
+    if dollars < 1 {
+        return price;
+    }
+    Money::from_cents(dollars * 100 - 1)
+}
+
+// the smallest x.99 that isn't below price
+fn charm_up(price: Money) -> Money {
+    Money::from_cents((price.cents() + 100) / 100 * 100 - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::obj_vd::VendooProduct;
+
+    fn money(str: &str) -> Money {
+        Money::parse(str).unwrap()
+    }
+
+    fn adjustment(str: &str) -> Adjustment {
+        Adjustment::try_from(str.to_owned()).unwrap()
+    }
+
+    fn rules(json: serde_json::Value) -> PriceRules {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn adjustments() {
+        assert_eq!(adjustment("-10%"), Adjustment::Percent(Decimal::from(-10)));
+        assert_eq!(adjustment("+2.50"), Adjustment::Fixed(money("2.50")));
+        assert_eq!(adjustment(" $-2 "), Adjustment::Fixed(money("-2")));
+        assert!(Adjustment::try_from(String::from("ten%")).is_err());
+
+        assert_eq!(adjustment("-10%").apply(money("19.99")), money("17.99"));
+        assert_eq!(adjustment("+15%").apply(money("10")), money("11.50"));
+        assert_eq!(adjustment("-2").apply(money("10")), money("8"));
+        assert_eq!(adjustment("-10%").debug(), "-10%");
+        assert_eq!(adjustment("2.5").debug(), "+2.50");
+    }
+
+    #[test]
+    fn charm_rounding() {
+        assert_eq!(charm_nearest(money("17.20")), money("16.99"));
+        assert_eq!(charm_nearest(money("17.60")), money("17.99"));
+        assert_eq!(charm_nearest(money("17.50")), money("17.99"));
+        assert_eq!(charm_nearest(money("0.40")), money("0.40"));
+        assert_eq!(charm_up(money("17.20")), money("17.99"));
+        assert_eq!(charm_up(money("16.99")), money("16.99"));
+        assert_eq!(charm_up(money("17.00")), money("17.99"));
+    }
+
+    #[test]
+    fn most_specific_category_rule_wins() {
+        let rules = rules(serde_json::json!({
+            "adjust": "-10%",
+            "charm": true,
+            "categories": [
+                { "match": "Men > *", "adjust": "-5%" },
+                { "match": "Men > Shoes > *", "charm": false },
+                { "match": "Men > Shoes > Boots", "sale": "-20%" },
+            ],
+        }));
+
+        let rule = rules.rule_for("Women > Tops");
+        assert_eq!(rule.adjust, Some(adjustment("-10%")));
+        assert_eq!(rule.charm, Some(true));
+
+        assert_eq!(rules.rule_for("men>tops").adjust, Some(adjustment("-5%")));
+
+        // fields the matching rule doesn't mention come from the default, not "Men > *"
+        let rule = rules.rule_for("Men > Shoes > Sneakers");
+        assert_eq!(rule.adjust, Some(adjustment("-10%")));
+        assert_eq!(rule.charm, Some(false));
+
+        let rule = rules.rule_for("Home, Men > Shoes > Boots");
+        assert_eq!(rule.sale, Some(adjustment("-20%")));
+        assert_eq!(rule.charm, Some(true));
+    }
+
+    #[test]
+    fn price_with_adjusts_charms_and_floors() {
+        let rule = PriceRule {
+            adjust: Some(adjustment("-10%")),
+            charm: Some(true),
+            min_margin: Some(adjustment("+50%")),
+            sale: None,
+        };
+        let trace = price_with(&rule, money("20"), Some(money("5"))).unwrap();
+        assert_eq!(trace.regular, money("17.99"));
+        assert_eq!(trace.steps, vec!["-10%", "charm"]);
+
+        // 20 -> 18 -> 17.99 is under cost 15 + 50%, so it goes up to the next x.99
+        let trace = price_with(&rule, money("20"), Some(money("15"))).unwrap();
+        assert_eq!(trace.regular, money("22.99"));
+        assert_eq!(trace.steps, vec!["-10%", "charm", "floor 22.50"]);
+
+        assert!(price_with(&PriceRule::default(), money("20"), None).is_none());
+    }
+
+    #[test]
+    fn sales_stay_below_regular_and_above_the_floor() {
+        let rule = PriceRule {
+            sale: Some(adjustment("-15%")),
+            ..Default::default()
+        };
+        let trace = price_with(&rule, money("40"), None).unwrap();
+        assert_eq!(
+            (trace.regular, trace.sale),
+            (money("40"), Some(money("34")))
+        );
+
+        let charmed = PriceRule {
+            charm: Some(true),
+            sale: Some(adjustment("-1%")),
+            ..Default::default()
+        };
+        // 19.99 - 1% charms back to 19.99, so it drops a dollar
+        let trace = price_with(&charmed, money("19.99"), None).unwrap();
+        assert_eq!(trace.sale, Some(money("18.99")));
+
+        let floored = PriceRule {
+            min_margin: Some(adjustment("+0%")),
+            sale: Some(adjustment("-50%")),
+            ..Default::default()
+        };
+        let trace = price_with(&floored, money("40"), Some(money("30"))).unwrap();
+        assert_eq!(trace.sale, None);
+        assert_eq!(trace.steps, vec!["no sale, below floor"]);
+
+        let raised = PriceRule {
+            sale: Some(adjustment("+5%")),
+            ..Default::default()
+        };
+        let trace = price_with(&raised, money("40"), None).unwrap();
+        assert_eq!(trace.sale, None);
+        assert_eq!(trace.steps, vec!["no sale, not below regular"]);
+    }
+
+    #[test]
+    fn apply_prices_once() {
+        let product: VendooProduct = serde_json::from_value(serde_json::json!({
+            "Title": "Boots",
+            "Category": "Men > Shoes > Boots",
+            "Price": "$100.00",
+        }))
+        .unwrap();
+        let mut objects = vec![LocalObject::from_vendoo_object(&product)];
+        let rules = rules(serde_json::json!({
+            "categories": [{ "match": "Men > Shoes > *", "adjust": "-10%" }],
+        }));
+
+        assert_eq!(rules.apply(&mut objects), 1);
+        assert_eq!(objects[0].regular_price, Some(money("90")));
+        // a second pass doesn't take another 10% off
+        assert_eq!(rules.apply(&mut objects), 0);
+        assert_eq!(objects[0].regular_price, Some(money("90")));
+    }
+}
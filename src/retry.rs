@@ -0,0 +1,464 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+use crate::obj_wc::ObjWooCommerce;
+use crate::wc_error::WcError;
+
+// how safe a request is to send twice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idempotency {
+    // GETs, PUTs, batch updates/deletes, term creates (term_exists hands back the id).
+    // retried on anything that looks transient.
+    Idempotent,
+    // creates. only retried when the store can't have acted on the first attempt: the
+    // connection never opened, or it answered 429/503 (turned away at the door). a 502 or
+    // a timeout could mean the product exists and a retry would make a second one.
+    IfNotProcessed,
+}
+
+// WC_MAX_ATTEMPTS (default 4, 1 turns retries off), WC_RETRY_BASE_MS (500) and
+// WC_RETRY_MAX_MS (60000) in .env. attempt n waits base * 2^(n-1), jittered, capped at max.
+// a Retry-After from the store wins over the backoff but is capped at max too.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        let var = |name: &str| -> Option<u64> {
+            let str = std::env::var(name).ok()?;
+            match str.trim().parse::<u64>() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    eprintln!("[] {} {} not understood, ignoring it", name, str);
+                    None
+                }
+            }
+        };
+        if let Some(max_attempts) = var("WC_MAX_ATTEMPTS") {
+            policy.max_attempts = max_attempts.max(1) as u32;
+        }
+        if let Some(base) = var("WC_RETRY_BASE_MS") {
+            policy.base_delay = Duration::from_millis(base);
+        }
+        if let Some(max) = var("WC_RETRY_MAX_MS") {
+            policy.max_delay = Duration::from_millis(max);
+        }
+        policy
+    }
+
+    // full backoff for the attempt that just failed (1-based), then somewhere in its upper half
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        let half = delay / 2;
+        let jitter = random_u64() % (half.as_millis() as u64 + 1);
+        half + Duration::from_millis(jitter)
+    }
+
+    fn should_retry_status(&self, status: StatusCode, idempotency: Idempotency) -> bool {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => true,
+            StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT => {
+                idempotency == Idempotency::Idempotent
+            }
+            _ => false,
+        }
+    }
+
+    fn should_retry_error(&self, e: &reqwest::Error, idempotency: Idempotency) -> bool {
+        e.is_connect() || (e.is_timeout() && idempotency == Idempotency::Idempotent)
+    }
+}
+
+// sends what build makes, and keeps sending it while policy and idempotency allow. the
+// last response comes back whatever its status, wc_error::check turns it into a WcError.
+pub async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    idempotency: Idempotency,
+    build: F,
+) -> Result<Response, WcError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt: u32 = 1;
+    loop {
+        let (client, request) = build().build_split();
+        let request = request?;
+        let label = format!("{} {}", request.method(), request.url().path());
+        let last = attempt >= policy.max_attempts;
+
+        let delay = match client.execute(request).await {
+            Ok(response) => {
+                if last || !policy.should_retry_status(response.status(), idempotency) {
+                    return Ok(response);
+                }
+                let delay = retry_after(&response)
+                    .map(|delay| delay.min(policy.max_delay))
+                    .unwrap_or_else(|| policy.backoff(attempt));
+                eprintln!(
+                    "[] {} got {}, retrying in {:?} (attempt {} of {})",
+                    label,
+                    response.status(),
+                    delay,
+                    attempt + 1,
+                    policy.max_attempts
+                );
+                delay
+            }
+            Err(e) => {
+                if last || !policy.should_retry_error(&e, idempotency) {
+                    return Err(e.into());
+                }
+                let delay = policy.backoff(attempt);
+                eprintln!(
+                    "[] {} failed ({}), retrying in {:?} (attempt {} of {})",
+                    label,
+                    e,
+                    delay,
+                    attempt + 1,
+                    policy.max_attempts
+                );
+                delay
+            }
+        };
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+impl ObjWooCommerce {
+    // every WooCommerce request goes through here so they all share one retry policy and
+    // get signed the store's way (see auth.rs) on every attempt
+    pub(crate) async fn send<F>(
+        &self,
+        idempotency: Idempotency,
+        build: F,
+    ) -> Result<Response, WcError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let authorized = || {
+            let (client, request) = build().build_split();
+            match request {
+                Ok(mut request) => {
+                    self.auth.authorize(&mut request, &self.ckey, &self.skey);
+                    RequestBuilder::from_parts(client, request)
+                }
+                // send_with_retry reports it
+                Err(_) => build(),
+            }
+        };
+        send_with_retry(&self.retry, idempotency, authorized).await
+    }
+
+    // the same for /wp/v2, which only takes a WordPress user and application password
+    pub(crate) async fn send_wp<F>(
+        &self,
+        idempotency: Idempotency,
+        build: F,
+    ) -> Result<Response, WcError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let credentials = self.wp_credentials.as_ref().ok_or_else(|| {
+            WcError::validation("WordPress calls need WP_USERNAME and WP_APP_PASSWORD in .env")
+        })?;
+        let authorized = || {
+            let (client, request) = build().build_split();
+            match request {
+                Ok(mut request) => {
+                    credentials.authorize(&mut request);
+                    RequestBuilder::from_parts(client, request)
+                }
+                Err(_) => build(),
+            }
+        };
+        send_with_retry(&self.retry, idempotency, authorized).await
+    }
+}
+
+// Retry-After is either seconds or an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// good enough for jitter (and OAuth nonces) and no extra dependency, RandomState is
+// seeded per instance
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    use reqwest::Client;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::obj_wc::WooCommerceProduct;
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(20),
+        }
+    }
+
+    fn store(server: &MockServer, max_attempts: u32) -> ObjWooCommerce {
+        let mut wc =
+            ObjWooCommerce::new_with_auth(server.uri(), String::from("ck"), String::from("cs"));
+        wc.retry = fast_policy(max_attempts);
+        wc
+    }
+
+    // fails with status the first `failures` times, then answers body
+    async fn flaky(
+        server: &MockServer,
+        verb: &str,
+        route: &str,
+        status: u16,
+        failures: u64,
+        body: serde_json::Value,
+    ) {
+        Mock::given(method(verb))
+            .and(path(route))
+            .respond_with(ResponseTemplate::new(status))
+            .up_to_n_times(failures)
+            .mount(server)
+            .await;
+        Mock::given(method(verb))
+            .and(path(route))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(server)
+            .await;
+    }
+
+    fn product(name: &str) -> WooCommerceProduct {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "regular_price": "10.00",
+            "description": "",
+            "categories": [],
+            "images": [],
+            "stock_quantity": null,
+            "status": "publish",
+            "sku": name,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn fetch_recovers_from_transient_failures() {
+        let server = MockServer::start().await;
+        flaky(
+            &server,
+            "GET",
+            "/wp-json/wc/v3/products",
+            503,
+            2,
+            serde_json::json!([]),
+        )
+        .await;
+
+        let products = store(&server, 4).fetch_all_products(|_| {}).await.unwrap();
+        assert!(products.is_empty());
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let server = MockServer::start().await;
+        flaky(
+            &server,
+            "GET",
+            "/wp-json/wc/v3/products",
+            502,
+            10,
+            serde_json::json!([]),
+        )
+        .await;
+
+        let err = store(&server, 3)
+            .fetch_all_products(|_| {})
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::BAD_GATEWAY));
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = MockServer::start().await;
+        flaky(
+            &server,
+            "GET",
+            "/wp-json/wc/v3/products",
+            401,
+            1,
+            serde_json::json!([]),
+        )
+        .await;
+
+        let err = store(&server, 4)
+            .fetch_all_products(|_| {})
+            .await
+            .unwrap_err();
+        assert!(err.is_auth());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn batch_creates_are_not_retried_after_a_bad_gateway() {
+        let server = MockServer::start().await;
+        flaky(
+            &server,
+            "POST",
+            "/wp-json/wc/v3/products/batch",
+            502,
+            1,
+            serde_json::json!({}),
+        )
+        .await;
+
+        let result = store(&server, 4)
+            .batch_products(vec![product("a")], Vec::new(), Vec::new())
+            .await;
+        assert_eq!(result.unwrap_err().status(), Some(StatusCode::BAD_GATEWAY));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn batch_updates_are_retried_after_a_bad_gateway() {
+        let server = MockServer::start().await;
+        let body = serde_json::json!({ "update": [{ "id": 7, "name": "a" }] });
+        flaky(
+            &server,
+            "POST",
+            "/wp-json/wc/v3/products/batch",
+            502,
+            2,
+            body,
+        )
+        .await;
+
+        let report = store(&server, 4)
+            .batch_products(
+                Vec::new(),
+                vec![serde_json::json!({ "id": 7, "regular_price": "9.00" })],
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.succeeded(), 1);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_creates_are_retried_after_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products/batch"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products/batch"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "create": [{ "id": 1, "name": "a" }] })),
+            )
+            .mount(&server)
+            .await;
+
+        let mut wc = store(&server, 4);
+        wc.retry.max_delay = Duration::from_secs(5);
+        let started = Instant::now();
+        let report = wc
+            .batch_products(vec![product("a")], Vec::new(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(report.succeeded(), 1);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn connection_refused_is_retried() {
+        // grab a port nothing listens on
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}/", port);
+        let client = Client::new();
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+
+        let result = send_with_retry(&fast_policy(3), Idempotency::IfNotProcessed, || {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            client.post(&url)
+        })
+        .await;
+        assert!(matches!(result, Err(WcError::Transport(_))));
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn backoff_grows_and_stays_under_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        for _ in 0..50 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = policy.backoff(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            assert!(policy.backoff(9) <= Duration::from_millis(1000));
+        }
+    }
+}
@@ -1,13 +1,23 @@
+use std::collections::BTreeMap;
 use std::io::Write;
 
 use crate::{
-    local::{self, LocalObject, LocalSession},
+    category_map::CategoryMap,
+    db::{is_session_json, LocalDb},
+    image_cache::ImageCache,
+    image_process::ImageProcessing,
+    local::{self, DelistPolicy, LocalObject, LocalSession},
     obj_vd::ObjVendoo,
-    obj_wc::ObjWooCommerce,
+    obj_wc::{BatchReport, ObjWooCommerce},
+    plan::SyncPlan,
+    pricing::PriceRules,
+    upload::upload_concurrency,
+    wc_error::WcError,
 };
 use dialoguer::{Input, Select};
 
 pub const CSV_PATH_FAILED: &str = "/home/fizbin/lair/proj/rust/hcrelay/asset/vendoo.csv";
+pub const NO_LOCAL_DB: &str = "no local db!";
 
 pub struct State {
     pub api_base: String,
@@ -32,19 +42,24 @@ impl State {
     pub async fn test_pipeline(&mut self) {
         println!("[] POPULATING ObjWc & ObjVd...");
 
-        self.wc = Some(ObjWooCommerce::new_with_auth(
+        let mut wc = ObjWooCommerce::new_with_auth(
             self.api_base.clone(),
             self.ckey.clone(),
             self.skey.clone(),
-        ));
-
-        self.vd = Some(
-            ObjVendoo::from_csv(&self.csv_path.clone().unwrap_or(CSV_PATH_FAILED.to_owned()))
-                .unwrap(),
         );
 
-        let _ = self.wc.clone().unwrap().fetch_populate_products().await;
-        println!("wc populated");
+        // without the CSV the menus still work, the vendoo ones read it when they need it
+        let csv_path = self.csv_path.clone().unwrap_or(CSV_PATH_FAILED.to_owned());
+        match ObjVendoo::from_csv(&csv_path) {
+            Ok(vd) => self.vd = Some(vd),
+            Err(e) => eprintln!("[] couldn't read the vendoo CSV {}: {}", csv_path, e),
+        }
+
+        match wc.fetch_populate_products().await {
+            Ok(()) => println!("wc populated"),
+            Err(e) => eprintln!("[] couldn't populate wc: {}", e),
+        }
+        self.wc = Some(wc);
 
         // --- TODO --- vendoo! read from CSV
 
@@ -59,19 +74,31 @@ impl State {
                     "Exit",
                 ])
                 .default(0)
-                .interact()
-                .unwrap();
+                .interact();
+            let option = match option {
+                Ok(option) => option,
+                Err(e) => {
+                    eprintln!("[] {}", e);
+                    return;
+                }
+            };
 
             match option {
                 0 => {
                     // WooCommerce options
-                    let _ = self.wc_options_term().await.unwrap();
+                    if let Err(e) = self.wc_options_term().await {
+                        eprintln!("[] {}", e);
+                    }
                 }
                 1 => {
-                    println!("todo!")
+                    if let Err(e) = self.db_options_term().await {
+                        eprintln!("[] {}", e);
+                    }
                 }
                 2 => {
-                    let _ = self.vd_options_term().await.unwrap();
+                    if let Err(e) = self.vd_options_term().await {
+                        eprintln!("[] {}", e);
+                    }
                 }
                 3 => {
                     // todo!
@@ -96,28 +123,27 @@ impl State {
                     "Fetch WooCommerce Library",
                     "Post a VendooProduct to WooCommerce",
                     "Batch upload Vendoo CSV to WooCommerce",
+                    "Push Vendoo changes to existing WooCommerce products",
+                    "Delist sold/archived Vendoo items from WooCommerce",
+                    "Dry-run sync plan (no writes)",
                     "Back",
                     "Exit",
                 ])
                 .default(0)
-                .interact()
-                .unwrap();
+                .interact()?;
 
             match option {
                 0 => {
                     println!("--- fetching WooCommerce lib ---");
-                    let lib = match self.wc.clone().unwrap().fetch_products_raw().await {
-                        Ok(lib) => lib,
-                        Err(e) => {
-                            return Err(e);
-                        }
-                    };
+                    let lib = self
+                        .store()?
+                        .fetch_all_products(|progress| println!("[] {}", progress.debug()))
+                        .await?;
                     let option = Select::new()
                         .with_prompt("The WooCommerce lib has been fetched. See now?")
                         .items(&["Yes", "No", "Back", "Exit"])
                         .default(0)
-                        .interact()
-                        .unwrap();
+                        .interact()?;
 
                     // println!("{:?}", lib);
 
@@ -128,25 +154,199 @@ impl State {
                             println!("{}", object.debug());
                         }
                         let mut s = String::new();
-                        std::io::stdin().read_line(&mut s).unwrap();
+                        std::io::stdin().read_line(&mut s)?;
                         std::mem::drop(s);
                     }
 
                     // --- TODO! ---
                 }
                 1 => {
-                    // TODO!
-                    todo!()
+                    println!("--- posting one Vendoo product to WooCommerce ---");
+                    let mut wc = self.wc_with_media()?;
+                    let mut local_session = self.fresh_local_session().await?;
+                    let (_, postable): (i32, Vec<LocalObject>) = local_session.compare_wc_vd();
+                    if postable.is_empty() {
+                        println!("[] nothing needs to be posted.");
+                        continue;
+                    }
+
+                    let items: Vec<String> = postable
+                        .iter()
+                        .map(|vp_object| format!("{} (SKU: {})", vp_object.name, vp_object.sku))
+                        .collect();
+                    let option = Select::new()
+                        .with_prompt("Which product?")
+                        .items(&items)
+                        .default(0)
+                        .interact()?;
+
+                    let report = wc.batch_post_local(vec![postable[option].clone()]).await?;
+                    println!("{}", report.debug());
+                    self.keep_caches(&wc);
+                    local_session.mark_synced(&report);
+                    self.save_local_session(&local_session);
+                    self.record_batch(&report);
                 }
                 2 => {
-                    // TODO!
-                    todo!()
+                    println!("--- batch uploading Vendoo CSV to WooCommerce ---");
+                    let mut wc = self.wc_with_media()?;
+                    let mut local_session = self.fresh_local_session().await?;
+                    let report = local_session.match_wc_vd();
+                    println!(
+                        "{}",
+                        report.debug(&local_session.local_vp, &local_session.local_wp)
+                    );
+                    let (n, mut postable): (i32, Vec<LocalObject>) = local_session.compare_wc_vd();
+                    // with a database the mapping from earlier runs has a say too, not just
+                    // this fetch
+                    if let Some(db) = self.open_local_db() {
+                        db.retain_need_posted(&mut postable)?;
+                    }
+                    println!(
+                        "[] {} matches, {} products need to be posted.",
+                        n,
+                        postable.len()
+                    );
+
+                    if postable.is_empty() {
+                        continue;
+                    }
+
+                    let option = Select::new()
+                        .with_prompt(format!("Upload {} products now?", postable.len()))
+                        .items(&[
+                            "Yes, through /products/batch",
+                            "Yes, one request per product (concurrent)",
+                            "No",
+                        ])
+                        .default(2)
+                        .interact()?;
+
+                    if option < 2 {
+                        let report = if option == 0 {
+                            wc.batch_post_local(postable).await?
+                        } else {
+                            upload_streamed(&mut wc, postable).await?
+                        };
+                        println!("{}", report.debug());
+                        self.keep_caches(&wc);
+                        local_session.mark_synced(&report);
+                        self.save_local_session(&local_session);
+                        self.record_batch(&report);
+                    }
                 }
                 3 => {
+                    println!("--- looking for changed Vendoo products ---");
+                    let mut wc = self.wc_with_media()?;
+                    let mut local_session = self.fresh_local_session().await?;
+                    let updates = local_session.compare_wc_vd_updates();
+                    for update in &updates {
+                        println!("{}", update.debug());
+                    }
+                    println!("[] {} products need to be updated.", updates.len());
+
+                    if updates.is_empty() {
+                        continue;
+                    }
+
+                    let option = Select::new()
+                        .with_prompt(format!("Update {} products now?", updates.len()))
+                        .items(&["Yes", "No"])
+                        .default(1)
+                        .interact()?;
+
+                    if option == 0 {
+                        let report = wc.batch_update_local(updates).await?;
+                        println!("{}", report.debug());
+                        self.keep_caches(&wc);
+                        local_session.mark_synced(&report);
+                        self.save_local_session(&local_session);
+                        self.record_batch(&report);
+                    }
+                }
+                4 => {
+                    println!("--- looking for sold/archived Vendoo products ---");
+                    let wc = self.store()?;
+                    let mut local_session = self.fresh_local_session().await?;
+                    let policy = DelistPolicy::from_env();
+                    let delistings = local_session.compare_wc_vd_delisted(policy);
+                    for delisting in &delistings {
+                        println!("{}", delisting.debug());
+                    }
+                    println!(
+                        "[] {} products need to be delisted ({:?}).",
+                        delistings.len(),
+                        policy.action
+                    );
+
+                    if delistings.is_empty() {
+                        continue;
+                    }
+
+                    let option = Select::new()
+                        .with_prompt(format!("Delist {} products now?", delistings.len()))
+                        .items(&["Yes", "No"])
+                        .default(1)
+                        .interact()?;
+
+                    if option == 0 {
+                        let report = wc.batch_delist(delistings).await?;
+                        println!("{}", report.debug());
+                        local_session.mark_synced(&report);
+                        self.save_local_session(&local_session);
+                        self.record_batch(&report);
+                    }
+                }
+                5 => {
+                    println!("--- building sync plan, nothing is written ---");
+                    let mut local_session = self.dry_run_local_session().await?;
+                    let plan = SyncPlan::build(&local_session, DelistPolicy::from_env());
+
+                    let option = Select::new()
+                        .with_prompt("Show the plan as")
+                        .items(&["Table", "JSON", "Save JSON to file"])
+                        .default(0)
+                        .interact()?;
+
+                    match option {
+                        0 => println!("{}", plan.to_table()),
+                        1 => println!("{}", plan.to_json()?),
+                        _ => {
+                            let path = Input::<String>::new()
+                                .with_prompt("Write plan JSON to")
+                                .default(String::from("hcrelay_plan.json"))
+                                .interact_text()?;
+                            std::fs::write(&path, plan.to_json()?)?;
+                            println!("[] plan written to {} ({})", path, plan.summary());
+                        }
+                    }
+
+                    if plan.is_noop() {
+                        println!("[] nothing to do.");
+                        continue;
+                    }
+
+                    let option = Select::new()
+                        .with_prompt(format!("Apply this plan? ({})", plan.summary()))
+                        .items(&["No", "Yes"])
+                        .default(0)
+                        .interact()?;
+
+                    if option == 1 {
+                        let mut wc = self.wc_with_media()?;
+                        let report = wc.apply_plan(&plan).await?;
+                        println!("{}", report.debug());
+                        self.keep_caches(&wc);
+                        local_session.mark_synced(&report);
+                        self.save_local_session(&local_session);
+                        self.record_batch(&report);
+                    }
+                }
+                6 => {
                     // go back to last menu!
                     break;
                 }
-                4 => {
+                7 => {
                     println!("bye!");
                     std::process::exit(0);
                 }
@@ -158,6 +358,224 @@ impl State {
         return Ok(());
     }
 
+    // reads the CSV if it hasn't been yet and pulls a fresh copy of the whole store, then
+    // records both, the matches and the session in LOCAL_DB
+    async fn fresh_local_session(&mut self) -> Result<LocalSession, Box<dyn std::error::Error>> {
+        let (vd, wc) = self.fetch_both().await?;
+
+        if let Some(mut db) = self.open_local_db() {
+            let import = db.import_vendoo(vd.products.as_deref().unwrap_or_default())?;
+            if !import.duplicates.is_empty() {
+                eprintln!("{}", import.debug());
+            }
+            db.import_woocommerce(wc.products.as_deref().unwrap_or_default())?;
+        }
+
+        let local_session = self.local_session_from(wc, vd);
+        if let Some(mut db) = self.open_local_db() {
+            db.record_matches(&local_session, &local_session.match_wc_vd())?;
+        }
+        self.save_local_session(&local_session);
+
+        Ok(local_session)
+    }
+
+    // the same session with nothing written locally, for the dry run
+    async fn dry_run_local_session(&mut self) -> Result<LocalSession, Box<dyn std::error::Error>> {
+        let (vd, wc) = self.fetch_both().await?;
+        Ok(self.local_session_from(wc, vd))
+    }
+
+    async fn fetch_both(
+        &mut self,
+    ) -> Result<(ObjVendoo, ObjWooCommerce), Box<dyn std::error::Error>> {
+        let vd = self.vendoo()?.clone();
+        let mut wc = self.store()?;
+        wc.fetch_populate_products_with_progress(|progress| println!("[] {}", progress.debug()))
+            .await?;
+        Ok((vd, wc))
+    }
+
+    // price rules, the category map and the sync times out of the last saved session,
+    // only reads
+    fn local_session_from(&self, wc: ObjWooCommerce, vd: ObjVendoo) -> LocalSession {
+        let mut local_session = LocalSession::from_session(wc, vd);
+        let repriced = PriceRules::from_env().apply(&mut local_session.local_vp);
+        if repriced > 0 {
+            println!("[] price rules changed {} vendoo prices", repriced);
+        }
+        let category_map = CategoryMap::from_env();
+        if !category_map.is_empty() {
+            println!("{}", category_map.apply(&mut local_session).debug());
+        }
+        if let Some(local_db) = self.session_path() {
+            if std::path::Path::new(local_db).exists() {
+                match LocalSession::load(local_db) {
+                    Ok(previous) => local_session.carry_sync_times(&previous),
+                    Err(e) => eprintln!("[] couldn't load previous session {}: {}", local_db, e),
+                }
+            }
+        }
+        local_session
+    }
+
+    // writes the session to LOCAL_DB if it's a .json file, or the batch results to the
+    // sqlite store if it isn't
+    fn save_local_session(&self, local_session: &LocalSession) {
+        if let Some(local_db) = self.session_path() {
+            match local_session.save(local_db) {
+                Ok(()) => println!("[] session saved to {}", local_db),
+                Err(e) => eprintln!("[] couldn't save session to {}: {}", local_db, e),
+            }
+        }
+    }
+
+    fn record_batch(&self, report: &BatchReport) {
+        if let Some(mut db) = self.open_local_db() {
+            if let Err(e) = db.record_batch(report) {
+                eprintln!("[] couldn't record sync history in {}: {}", db.path, e);
+            }
+        }
+    }
+
+    // the store client. the menus are only reachable once it's set up, but if they're not
+    // this is an error to print rather than a panic.
+    fn store(&self) -> Result<ObjWooCommerce, WcError> {
+        self.wc
+            .clone()
+            .ok_or_else(|| WcError::validation("WooCommerce isn't set up, restart hcrelay"))
+    }
+
+    // keep the category tree, attributes and tags a working copy of the store fetched/grew
+    fn keep_caches(&mut self, wc: &ObjWooCommerce) {
+        if let Some(store) = self.wc.as_mut() {
+            store.categories = wc.categories.clone();
+            store.attributes = wc.attributes.clone();
+            store.tags = wc.tags.clone();
+        }
+    }
+
+    // the vendoo CSV, read now if it hasn't been. asks for the path if none is configured.
+    fn vendoo(&mut self) -> Result<&mut ObjVendoo, Box<dyn std::error::Error>> {
+        let csv_path = match &self.csv_path {
+            Some(csv_path) => csv_path.clone(),
+            None => {
+                let csv_path = Input::<String>::new()
+                    .with_prompt("No CSV file has been configured. Enter CSV file path:")
+                    .interact_text()?;
+                self.csv_path = Some(csv_path.clone());
+                csv_path
+            }
+        };
+        match self.vd.as_mut() {
+            Some(vd) if vd.products.is_none() => vd.existing_from_csv(&csv_path)?,
+            Some(_) => {}
+            None => self.vd = Some(ObjVendoo::from_csv(&csv_path)?),
+        }
+        self.vd
+            .as_mut()
+            .ok_or_else(|| format!("couldn't read the vendoo CSV {}", csv_path).into())
+    }
+
+    // a copy of the store client that uploads whatever the image cache holds to the media
+    // library (processed per IMAGE_* in .env) instead of letting WooCommerce sideload from
+    // vendoo's CDN
+    fn wc_with_media(&self) -> Result<ObjWooCommerce, WcError> {
+        let mut wc = self.store()?;
+        match ImageCache::from_env() {
+            Ok(cache) => wc.image_cache = Some(cache),
+            Err(e) => eprintln!("[] no image cache, images will be sideloaded: {}", e),
+        }
+        let processing = ImageProcessing::from_env();
+        println!("{}", processing.debug());
+        wc.image_processing = Some(processing);
+        Ok(wc)
+    }
+
+    fn session_path(&self) -> Option<&String> {
+        self.local_db.as_ref().filter(|path| is_session_json(path))
+    }
+
+    fn open_local_db(&self) -> Option<LocalDb> {
+        let path = self
+            .local_db
+            .as_ref()
+            .filter(|path| !is_session_json(path))?;
+        match LocalDb::open(path) {
+            Ok(db) => Some(db),
+            Err(e) => {
+                eprintln!("[] couldn't open local db {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    pub async fn db_options_term(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            let option = Select::new()
+                .with_prompt("DATABASE MENU")
+                .items(&[
+                    "Import Vendoo CSV + WooCommerce store into database",
+                    "Vendoo products not yet in WooCommerce",
+                    "WooCommerce products to delist",
+                    "Sync history",
+                    "Back",
+                    "Exit",
+                ])
+                .default(0)
+                .interact()?;
+
+            if option == 4 {
+                break;
+            }
+            if option == 5 {
+                println!("bye!");
+                std::process::exit(0);
+            }
+
+            let Some(db) = self.open_local_db() else {
+                println!("[] LOCAL_DB isn't set to a database file (anything but .json).");
+                continue;
+            };
+
+            match option {
+                0 => {
+                    // imports both sides and records the matches as a side effect
+                    self.fresh_local_session().await?;
+                    println!("{}", db.debug()?);
+                }
+                1 => {
+                    let products = db.need_posted()?;
+                    for product in &products {
+                        println!("{}", product.debug());
+                    }
+                    println!("[] {} products need to be posted.", products.len());
+                }
+                2 => {
+                    let products = db.need_delisting(DelistPolicy::from_env())?;
+                    for (wc_id, product) in &products {
+                        println!(
+                            "#{} {} ({})",
+                            wc_id,
+                            product.title.clone().unwrap_or_default(),
+                            product.status.clone().unwrap_or_default()
+                        );
+                    }
+                    println!("[] {} products need to be delisted.", products.len());
+                }
+                3 => {
+                    for row in db.history(50)? {
+                        println!("{}", row.debug());
+                    }
+                }
+                _ => {
+                    //
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn vd_options_term(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         loop {
             let option = Select::new()
@@ -165,51 +583,26 @@ impl State {
                 .items(&[
                     "Display all Vendoo products",
                     "General CSV information",
+                    "Cache Vendoo images locally",
                     "Back",
                     "Exit",
                 ])
                 .default(0)
-                .interact()
-                .unwrap();
+                .interact()?;
 
             match option {
                 0 => {
                     println!("--- deserializing Vendoo products... ---");
-
-                    if self.vd.is_none() {
-                        if !self.csv_path.is_some() {
-                            self.csv_path = Some(
-                                Input::<String>::new()
-                                    .with_prompt(
-                                        "No CSV file has been configured. Enter CSV file path:",
-                                    )
-                                    .interact_text()
-                                    .unwrap(),
-                            );
-                        } else {
-                            self.vd =
-                                Some(ObjVendoo::from_csv(&self.csv_path.clone().unwrap()).unwrap());
-                        }
-                    } else {
-                        if self.vd.clone().unwrap().products.is_none() {
-                            self.vd
-                                .as_mut()
-                                .expect("no vd!")
-                                .existing_from_csv(&self.csv_path.clone().unwrap())
-                                .unwrap()
-                            // reconstruct
-                        }
-                    }
+                    let vd = self.vendoo()?;
 
                     let option = Select::new()
                         .with_prompt("The Vendoo lib has been fetched. See now?")
                         .items(&["Yes", "No", "Back", "Exit"])
                         .default(0)
-                        .interact()
-                        .unwrap();
+                        .interact()?;
 
                     if option == 0 {
-                        for vendoo_prod in self.vd.clone().unwrap().products.unwrap() {
+                        for vendoo_prod in vd.products.iter().flatten() {
                             println!("{:?}", vendoo_prod);
                         }
                     }
@@ -217,12 +610,33 @@ impl State {
                     // --- TODO! ---
                 }
                 1 => {
-                    // TODO!
-                    todo!()
+                    let csv_path = self.csv_path.clone().unwrap_or_default();
+                    let products = self.vendoo()?.products.clone().unwrap_or_default();
+                    let mut statuses: BTreeMap<String, usize> = BTreeMap::new();
+                    for product in &products {
+                        *statuses
+                            .entry(product.status.clone().unwrap_or_default())
+                            .or_default() += 1;
+                    }
+                    println!("[] {}: {} products", csv_path, products.len());
+                    for (status, n) in &statuses {
+                        println!("[]   {}: {}", status, n);
+                    }
+                    println!(
+                        "[] {} without a SKU, {} without images",
+                        products.iter().filter(|p| p.sku.is_none()).count(),
+                        products
+                            .iter()
+                            .filter(|p| p.image_urls().is_empty())
+                            .count()
+                    );
                 }
                 2 => {
-                    // TODO!
-                    todo!()
+                    let mut cache = ImageCache::from_env()?;
+                    println!("[] caching Vendoo images into {}", cache.dir.display());
+                    let store = self.store()?;
+                    let report = self.vendoo()?.cache_images(&mut cache, &store).await?;
+                    println!("{}", report.debug());
                 }
                 3 => {
                     // go back to last menu!
@@ -248,37 +662,26 @@ impl State {
         press_enter_to_continue(String::from("create new wc and vendoo!"));
 
         println!("[]creating new wc");
-        self.wc = Some(ObjWooCommerce::new_with_auth(
+        let mut wc = ObjWooCommerce::new_with_auth(
             self.api_base.clone(),
             self.ckey.clone(),
             self.skey.clone(),
-        ));
+        );
         println!("done");
 
         println!("[]populating instance from store");
-        match self.wc.as_mut().unwrap().fetch_populate_products().await {
-            Ok(lib) => lib,
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        wc.fetch_populate_products().await?;
         println!("done");
 
         println!("[]populating lib var from store");
-        let lib = match self.wc.clone().unwrap().fetch_products_raw().await {
-            Ok(lib) => lib,
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        let lib = wc.fetch_products_raw().await?;
+        self.wc = Some(wc.clone());
 
         println!("done");
 
         println!("[]creating new vd from csv");
-        self.vd = Some(
-            ObjVendoo::from_csv(&self.csv_path.clone().unwrap_or(CSV_PATH_FAILED.to_owned()))
-                .unwrap(),
-        );
+        let vd = ObjVendoo::from_csv(&self.csv_path.clone().unwrap_or(CSV_PATH_FAILED.to_owned()))?;
+        self.vd = Some(vd.clone());
         println!("done");
 
         press_enter_to_continue(String::from("debug raw woocommerce objects"));
@@ -294,7 +697,7 @@ impl State {
 
         press_enter_to_continue(String::from("debug *parsed* woocommerce objects"));
 
-        for obj in self.wc.clone().unwrap().products.unwrap() {
+        for obj in wc.products.iter().flatten() {
             println!("WC PARSED OBJECT #{}\n{}", idx, obj.debug());
             idx += 1;
         }
@@ -303,7 +706,7 @@ impl State {
 
         press_enter_to_continue(String::from("debug woocommerce entries"));
 
-        for obj in self.vd.clone().unwrap().products.unwrap() {
+        for obj in vd.products.iter().flatten() {
             println!("VENDOO PARSED OBJECT #{}\n{}", idx, obj.debug());
             idx += 1;
         }
@@ -312,8 +715,7 @@ impl State {
 
         press_enter_to_continue(String::from("create LocalSession"));
 
-        let mut local_session =
-            LocalSession::from_session(self.wc.clone().unwrap(), self.vd.clone().unwrap());
+        let local_session = LocalSession::from_session(wc, vd);
 
         press_enter_to_continue(String::from("print out all wc_vec Titles, IDX and SKU's"));
 
@@ -340,6 +742,12 @@ impl State {
             "compare WC to VD and find objects that need to be posted!",
         ));
 
+        let report = local_session.match_wc_vd();
+        println!(
+            "{}",
+            report.debug(&local_session.local_vp, &local_session.local_wp)
+        );
+
         let (n, postable): (i32, Vec<LocalObject>) = local_session.compare_wc_vd();
 
         println!("{} products need to be posted.", postable.len());
@@ -357,3 +765,49 @@ pub fn press_enter_to_continue(str: String) {
     std::io::stdin().read_line(&mut s).unwrap();
     std::mem::drop(s);
 }
+
+// upload_local_concurrently with each product printed as it finishes. the first Ctrl-C
+// stops sending new ones, a second one quits without waiting for the rest.
+async fn upload_streamed(
+    wc: &mut ObjWooCommerce,
+    postable: Vec<LocalObject>,
+) -> Result<BatchReport, Box<dyn std::error::Error>> {
+    let concurrency = upload_concurrency();
+    println!("[] uploading {} at a time, Ctrl-C to stop", concurrency);
+    let mut handle = wc.upload_local_concurrently(postable, concurrency).await?;
+    let mut report = BatchReport::default();
+    let mut done: usize = 0;
+    let mut cancelled: usize = 0;
+
+    loop {
+        let result = tokio::select! {
+            result = handle.next() => result,
+            _ = tokio::signal::ctrl_c() => {
+                if handle.is_cancelled() {
+                    println!("[] quitting, products in flight may still be created");
+                    std::process::exit(130);
+                }
+                println!("[] cancelling, waiting for the uploads in flight...");
+                handle.cancel();
+                continue;
+            }
+        };
+        let Some(result) = result else {
+            break;
+        };
+        done += 1;
+        println!("[] {}/{} {}", done, handle.total, result.debug());
+        match result.to_batch_item() {
+            Some(item) => report.results.push(item),
+            None => cancelled += 1,
+        }
+    }
+
+    if cancelled > 0 {
+        println!(
+            "[] {} products were cancelled before they went up",
+            cancelled
+        );
+    }
+    Ok(report)
+}
@@ -0,0 +1,204 @@
+use std::sync::OnceLock;
+
+// post statuses and stock statuses as WooCommerce spells them
+pub const WC_STATUSES: [&str; 5] = ["publish", "draft", "pending", "private", "trash"];
+pub const WC_STOCK_STATUSES: [&str; 3] = ["instock", "outofstock", "onbackorder"];
+
+// where a vendoo status ends up. either half can be missing, "Sold=outofstock" leaves the
+// post status alone and only marks the product out of stock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusMapping {
+    pub vendoo: String,
+    pub status: Option<String>,
+    pub stock_status: Option<String>,
+}
+
+// STATUS_MAP in .env, comma separated vendoo=status/stock_status pairs. entries override
+// the defaults below one vendoo status at a time:
+//
+//     STATUS_MAP="Sold=trash, Draft=private, Archived=draft/outofstock"
+#[derive(Debug, Clone)]
+pub struct StatusMap {
+    pub mappings: Vec<StatusMapping>,
+}
+
+impl Default for StatusMap {
+    // Active=publish/instock, Sold=outofstock, Draft=draft
+    fn default() -> Self {
+        let mapping =
+            |vendoo: &str, status: Option<&str>, stock_status: Option<&str>| StatusMapping {
+                vendoo: vendoo.to_owned(),
+                status: status.map(|status| status.to_owned()),
+                stock_status: stock_status.map(|stock_status| stock_status.to_owned()),
+            };
+        Self {
+            mappings: vec![
+                mapping("Active", Some("publish"), Some("instock")),
+                mapping("Sold", None, Some("outofstock")),
+                mapping("Draft", Some("draft"), None),
+            ],
+        }
+    }
+}
+
+impl StatusMap {
+    pub fn parse(str: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for pair in str
+            .split(',')
+            .map(|pair| pair.trim())
+            .filter(|pair| !pair.is_empty())
+        {
+            let (vendoo, wc) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} isn't vendoo=status", pair))?;
+            let vendoo = vendoo.trim();
+            if vendoo.is_empty() {
+                return Err(format!("{:?} has no vendoo status", pair));
+            }
+
+            let mut status: Option<String> = None;
+            let mut stock_status: Option<String> = None;
+            for part in wc.split('/').map(|part| part.trim().to_lowercase()) {
+                if WC_STATUSES.contains(&part.as_str()) && status.is_none() {
+                    status = Some(part);
+                } else if WC_STOCK_STATUSES.contains(&part.as_str()) && stock_status.is_none() {
+                    stock_status = Some(part);
+                } else {
+                    return Err(format!(
+                        "{:?} in {:?} isn't a WooCommerce status or stock status",
+                        part, pair
+                    ));
+                }
+            }
+
+            let mapping = StatusMapping {
+                vendoo: vendoo.to_owned(),
+                status,
+                stock_status,
+            };
+            match map.position(vendoo) {
+                Some(idx) => map.mappings[idx] = mapping,
+                None => map.mappings.push(mapping),
+            }
+        }
+        Ok(map)
+    }
+
+    // STATUS_MAP if it's set and parses, the defaults otherwise
+    pub fn from_env() -> Self {
+        match std::env::var("STATUS_MAP") {
+            Ok(str) => match Self::parse(&str) {
+                Ok(map) => map,
+                Err(e) => {
+                    eprintln!("[] STATUS_MAP not understood, using the defaults: {}", e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn position(&self, vendoo: &str) -> Option<usize> {
+        self.mappings
+            .iter()
+            .position(|mapping| mapping.vendoo.eq_ignore_ascii_case(vendoo.trim()))
+    }
+
+    // vendoo -> WooCommerce, None for statuses the map doesn't know
+    pub fn to_wc(&self, vendoo: &str) -> Option<&StatusMapping> {
+        self.position(vendoo).map(|idx| &self.mappings[idx])
+    }
+
+    // WooCommerce -> vendoo, for reports. a mapping matches when every half it sets matches,
+    // and the one that sets the most wins, so publish/outofstock reads as Sold rather than
+    // Active.
+    pub fn to_vendoo(&self, status: &str, stock_status: Option<&str>) -> Option<&str> {
+        let mut best: Option<(&StatusMapping, usize)> = None;
+        for mapping in &self.mappings {
+            let mut specificity: usize = 0;
+            if let Some(mapped) = &mapping.status {
+                if mapped != status {
+                    continue;
+                }
+                specificity += 1;
+            }
+            if let Some(mapped) = &mapping.stock_status {
+                if Some(mapped.as_str()) != stock_status {
+                    continue;
+                }
+                specificity += 1;
+            }
+            if specificity > 0 && best.is_none_or(|(_, best)| specificity > best) {
+                best = Some((mapping, specificity));
+            }
+        }
+        best.map(|(mapping, _)| mapping.vendoo.as_str())
+    }
+}
+
+// loaded once, like the tag denylist
+pub fn status_map() -> &'static StatusMap {
+    static STATUS_MAP: OnceLock<StatusMap> = OnceLock::new();
+    STATUS_MAP.get_or_init(StatusMap::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn halves(mapping: Option<&StatusMapping>) -> (Option<&str>, Option<&str>) {
+        let mapping = mapping.unwrap();
+        (mapping.status.as_deref(), mapping.stock_status.as_deref())
+    }
+
+    #[test]
+    fn defaults_map_forward() {
+        let map = StatusMap::default();
+        assert_eq!(
+            halves(map.to_wc("Active")),
+            (Some("publish"), Some("instock"))
+        );
+        assert_eq!(halves(map.to_wc(" sold ")), (None, Some("outofstock")));
+        assert_eq!(halves(map.to_wc("DRAFT")), (Some("draft"), None));
+        assert!(map.to_wc("Archived").is_none());
+    }
+
+    #[test]
+    fn status_map_overrides_one_status_at_a_time() {
+        let map = StatusMap::parse("Sold=trash, archived = Draft/OutOfStock,,").unwrap();
+        assert_eq!(halves(map.to_wc("Sold")), (Some("trash"), None));
+        assert_eq!(
+            halves(map.to_wc("Archived")),
+            (Some("draft"), Some("outofstock"))
+        );
+        // untouched defaults stay
+        assert_eq!(
+            halves(map.to_wc("Active")),
+            (Some("publish"), Some("instock"))
+        );
+        assert_eq!(map.mappings.len(), 4);
+    }
+
+    #[test]
+    fn bad_status_maps_are_refused() {
+        assert!(StatusMap::parse("Sold").is_err());
+        assert!(StatusMap::parse("=draft").is_err());
+        assert!(StatusMap::parse("Sold=gone").is_err());
+        assert!(StatusMap::parse("Sold=draft/trash").is_err());
+    }
+
+    #[test]
+    fn reverse_mapping_takes_the_most_specific() {
+        let map = StatusMap::default();
+        assert_eq!(map.to_vendoo("publish", Some("instock")), Some("Active"));
+        assert_eq!(map.to_vendoo("publish", Some("outofstock")), Some("Sold"));
+        assert_eq!(map.to_vendoo("draft", Some("instock")), Some("Draft"));
+        assert_eq!(map.to_vendoo("publish", None), None);
+        assert_eq!(map.to_vendoo("pending", Some("onbackorder")), None);
+
+        let map = StatusMap::parse("Archived=draft/outofstock").unwrap();
+        assert_eq!(map.to_vendoo("draft", Some("outofstock")), Some("Archived"));
+        assert_eq!(map.to_vendoo("draft", Some("instock")), Some("Draft"));
+    }
+}
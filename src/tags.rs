@@ -0,0 +1,214 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::obj_wc::{normalize_term, ObjWooCommerce, Tag, WC_PER_PAGE};
+use crate::retry::Idempotency;
+use crate::wc_error::{self, WcError};
+
+// a term from /products/tags
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WcTag {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub slug: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TagCache {
+    pub tags: Vec<WcTag>,
+}
+
+impl TagCache {
+    pub fn find(&self, name: &str) -> Option<&WcTag> {
+        let name = normalize_term(name);
+        self.tags
+            .iter()
+            .find(|tag| normalize_term(&tag.name) == name)
+    }
+}
+
+impl ObjWooCommerce {
+    // every page of /products/tags
+    pub async fn fetch_tags(&self) -> Result<Vec<WcTag>, WcError> {
+        let url = self.endpoint("products/tags");
+        let client = &self.client;
+        let mut tags: Vec<WcTag> = Vec::new();
+        let mut page: u32 = 1;
+
+        loop {
+            let response = self
+                .send(Idempotency::Idempotent, || {
+                    client
+                        .get(&url)
+                        .query(&[("per_page", WC_PER_PAGE), ("page", page)])
+                })
+                .await?;
+
+            let context = format!("fetch tags (page {})", page);
+            let mut batch: Vec<WcTag> = wc_error::read_json(response, context).await?;
+            let batch_len = batch.len();
+            tags.append(&mut batch);
+            if batch_len < WC_PER_PAGE as usize {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(tags)
+    }
+
+    pub async fn create_tag(&self, name: &str) -> Result<WcTag, WcError> {
+        let url = self.endpoint("products/tags");
+        let client = &self.client;
+
+        let response = self
+            .send(Idempotency::Idempotent, || {
+                client.post(&url).json(&serde_json::json!({ "name": name }))
+            })
+            .await?;
+
+        let context = format!("create tag {}", name);
+        let error = match wc_error::read_json(response, context).await {
+            Ok(tag) => return Ok(tag),
+            Err(e) => e,
+        };
+        if let (Some("term_exists"), Some(id)) = (error.api_code(), error.resource_id()) {
+            return Ok(WcTag {
+                id,
+                name: name.to_owned(),
+                slug: String::new(),
+            });
+        }
+        Err(error)
+    }
+
+    // swaps name-only tags for ids, creating the ones the store doesn't have yet
+    pub async fn resolve_product_tags(&mut self, tags: &mut Vec<Tag>) -> Result<(), WcError> {
+        if tags.iter().all(|tag| tag.id.is_some()) {
+            return Ok(());
+        }
+        if self.tags.is_none() {
+            self.tags = Some(TagCache {
+                tags: self.fetch_tags().await?,
+            });
+        }
+
+        let mut resolved: Vec<Tag> = Vec::new();
+        for tag in std::mem::take(tags) {
+            let id = match tag.id {
+                Some(id) => id,
+                None => match self.tags.as_ref().unwrap().find(&tag.name) {
+                    Some(existing) => existing.id,
+                    None => {
+                        let created = self.create_tag(&tag.name).await?;
+                        println!("[] created WooCommerce tag {} (#{})", tag.name, created.id);
+                        let id = created.id;
+                        self.tags.as_mut().unwrap().tags.push(created);
+                        id
+                    }
+                },
+            };
+            if !resolved.iter().any(|existing| existing.id == Some(id)) {
+                resolved.push(Tag {
+                    id: Some(id),
+                    name: tag.name,
+                });
+            }
+        }
+
+        *tags = resolved;
+        Ok(())
+    }
+
+    // same thing for the "tags" array of an update patch
+    pub async fn resolve_patch_tags(
+        &mut self,
+        patch: &mut serde_json::Value,
+    ) -> Result<(), WcError> {
+        let tags = match patch.get("tags") {
+            Some(tags) => tags.clone(),
+            None => return Ok(()),
+        };
+        let mut tags: Vec<Tag> = wc_error::from_value(tags, "patch tags")?;
+        self.resolve_product_tags(&mut tags).await?;
+        patch["tags"] = serde_json::json!(tags);
+        Ok(())
+    }
+}
+
+// " Y2K,  streetwear ;#Vintage" -> ["y2k", "streetwear", "vintage"], duplicates dropped
+pub fn split_tags(cells: &[&Option<String>]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for cell in cells.iter().filter_map(|cell| cell.as_deref()) {
+        for tag in cell.split([',', ';', '\n', '|']) {
+            let tag = normalize_term(tag.trim().trim_start_matches('#'));
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+    tags
+}
+
+// TAG_DENYLIST in .env, comma separated ("needs photos, to ship"). labels on it are ours
+// only and never get posted as tags.
+pub fn tag_denylist() -> &'static [String] {
+    static DENYLIST: OnceLock<Vec<String>> = OnceLock::new();
+    DENYLIST.get_or_init(|| parse_denylist(&std::env::var("TAG_DENYLIST").unwrap_or_default()))
+}
+
+fn parse_denylist(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(normalize_term)
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+pub fn is_public_tag(tag: &str) -> bool {
+    !is_denied(tag, tag_denylist())
+}
+
+fn is_denied(tag: &str, denylist: &[String]) -> bool {
+    denylist.contains(&normalize_term(tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(str: &str) -> Option<String> {
+        Some(String::from(str))
+    }
+
+    #[test]
+    fn tags_split_on_every_separator() {
+        let tags = cell(" Y2K,  streetwear ;#Vintage|denim\nRetro  Wear");
+        assert_eq!(
+            split_tags(&[&tags]),
+            vec!["y2k", "streetwear", "vintage", "denim", "retro wear"]
+        );
+    }
+
+    #[test]
+    fn tags_are_deduplicated_across_cells() {
+        let tags = cell("Vintage, vintage ,, ;");
+        let labels = cell("#VINTAGE; Tops &  Tees, tops & tees");
+        assert_eq!(
+            split_tags(&[&tags, &None, &labels]),
+            vec!["vintage", "tops & tees"]
+        );
+        assert!(split_tags(&[&None, &cell(" , ")]).is_empty());
+    }
+
+    #[test]
+    fn denylisted_tags_are_private() {
+        let denylist = parse_denylist("Needs Photos,  to  ship ,,");
+        assert_eq!(denylist, vec!["needs photos", "to ship"]);
+        assert!(is_denied("needs photos", &denylist));
+        assert!(is_denied(" To Ship", &denylist));
+        assert!(!is_denied("vintage", &denylist));
+        assert!(parse_denylist("").is_empty());
+    }
+}
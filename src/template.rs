@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::obj_vd::{ObjVendoo, VendooProduct};
+
+// a description template. {{field}} is any VendooProduct column in snake_case (title,
+// brand, condition, primary_color, description, price, sku...), and a missing or empty
+// field renders as nothing, so wrap the parts that need it in a conditional:
+//
+//     <h2>{{title}}</h2>
+//     {{#if brand}}<p>Brand: {{brand}}</p>{{/if}}
+//     {{#if condition}}<p>Condition: {{condition}}</p>{{else}}<p>Pre-owned</p>{{/if}}
+//     {{#unless primary_color}}<p>Color as pictured</p>{{/unless}}
+//     {{description}}
+//
+// .md and .markdown files are Markdown and get turned into HTML after rendering, anything
+// else is HTML. field values are escaped on the way in either way, for Markdown its
+// metacharacters too.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub path: String,
+    markdown: bool,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Field(String),
+    If {
+        field: String,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+// what a block ended on, parse_nodes uses it to hand control back to the enclosing #if
+enum Closer {
+    Else,
+    EndIf,
+    EndUnless,
+    Eof,
+}
+
+impl Template {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let source = std::fs::read_to_string(path)?;
+        let lower = path.to_lowercase();
+        let markdown = lower.ends_with(".md") || lower.ends_with(".markdown");
+        Self::parse(&source, markdown)
+            .map(|mut template| {
+                template.path = path.to_owned();
+                template
+            })
+            .map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    pub fn parse(source: &str, markdown: bool) -> Result<Self, String> {
+        let mut rest = source;
+        let (nodes, closer) = parse_nodes(&mut rest)?;
+        match closer {
+            Closer::Eof => Ok(Self {
+                path: String::new(),
+                markdown,
+                nodes,
+            }),
+            Closer::Else => Err(String::from("{{else}} outside of an {{#if}}")),
+            Closer::EndIf => Err(String::from("{{/if}} without an {{#if}}")),
+            Closer::EndUnless => Err(String::from("{{/unless}} without an {{#unless}}")),
+        }
+    }
+
+    pub fn render(&self, product: &VendooProduct) -> String {
+        let fields = template_fields(product);
+        let mut out = String::new();
+        render_nodes(&self.nodes, &fields, self.markdown, &mut out);
+
+        if !self.markdown {
+            return out.trim().to_owned();
+        }
+        let mut html = String::new();
+        let parser = pulldown_cmark::Parser::new_ext(
+            &out,
+            pulldown_cmark::Options::ENABLE_TABLES | pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+        );
+        pulldown_cmark::html::push_html(&mut html, parser);
+        html.trim().to_owned()
+    }
+}
+
+fn parse_nodes(rest: &mut &str) -> Result<(Vec<Node>, Closer), String> {
+    let mut nodes: Vec<Node> = Vec::new();
+
+    loop {
+        let start = match rest.find("{{") {
+            Some(start) => start,
+            None => {
+                if !rest.is_empty() {
+                    nodes.push(Node::Text(rest.to_string()));
+                }
+                *rest = "";
+                return Ok((nodes, Closer::Eof));
+            }
+        };
+        if start > 0 {
+            nodes.push(Node::Text(rest[..start].to_owned()));
+        }
+        let end = rest[start..]
+            .find("}}")
+            .map(|end| start + end)
+            .ok_or_else(|| String::from("unclosed {{"))?;
+        let tag = rest[start + 2..end].trim().to_owned();
+        *rest = &rest[end + 2..];
+
+        if tag == "else" {
+            return Ok((nodes, Closer::Else));
+        }
+        if tag == "/if" {
+            return Ok((nodes, Closer::EndIf));
+        }
+        if tag == "/unless" {
+            return Ok((nodes, Closer::EndUnless));
+        }
+
+        let (negate, field) = if let Some(field) = tag.strip_prefix("#if ") {
+            (false, field)
+        } else if let Some(field) = tag.strip_prefix("#unless ") {
+            (true, field)
+        } else {
+            if tag.is_empty() || tag.contains(char::is_whitespace) || tag.starts_with(['#', '/']) {
+                return Err(format!("not a field: {{{{{}}}}}", tag));
+            }
+            nodes.push(Node::Field(tag));
+            continue;
+        };
+        let field = field.trim().to_owned();
+        let expected = if negate { "/unless" } else { "/if" };
+
+        let (then, closer) = parse_nodes(rest)?;
+        let otherwise = match closer {
+            Closer::Else => {
+                let (otherwise, closer) = parse_nodes(rest)?;
+                check_closer(&closer, negate, &field, expected)?;
+                otherwise
+            }
+            closer => {
+                check_closer(&closer, negate, &field, expected)?;
+                Vec::new()
+            }
+        };
+        nodes.push(Node::If {
+            field,
+            negate,
+            then,
+            otherwise,
+        });
+    }
+}
+
+fn check_closer(closer: &Closer, negate: bool, field: &str, expected: &str) -> Result<(), String> {
+    match (closer, negate) {
+        (Closer::EndIf, false) | (Closer::EndUnless, true) => Ok(()),
+        _ => Err(format!("{} is missing its {{{{{}}}}}", field, expected)),
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    fields: &BTreeMap<String, String>,
+    markdown: bool,
+    out: &mut String,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Field(field) => {
+                let value = fields.get(field).map(|value| value.as_str()).unwrap_or("");
+                // markdown keeps its own line breaks, entities survive it either way
+                if markdown {
+                    out.push_str(&escape_html(&escape_markdown(value)));
+                } else {
+                    out.push_str(&escape_html(value).replace('\n', "<br>\n"));
+                }
+            }
+            Node::If {
+                field,
+                negate,
+                then,
+                otherwise,
+            } => {
+                if fields.contains_key(field) != *negate {
+                    render_nodes(then, fields, markdown, out);
+                } else {
+                    render_nodes(otherwise, fields, markdown, out);
+                }
+            }
+        }
+    }
+}
+
+// every non-empty column of the product by its snake_case name, "Primary Color" ->
+// primary_color. prices come through in WooCommerce's format.
+pub fn template_fields(product: &VendooProduct) -> BTreeMap<String, String> {
+    let mut fields: BTreeMap<String, String> = BTreeMap::new();
+    let value = serde_json::to_value(product).unwrap_or_default();
+    if let Some(columns) = value.as_object() {
+        for (column, value) in columns {
+            let value = match value {
+                serde_json::Value::String(str) => str.trim().to_owned(),
+                serde_json::Value::Number(number) => number.to_string(),
+                serde_json::Value::Bool(bool) => bool.to_string(),
+                _ => continue,
+            };
+            if value.is_empty() {
+                continue;
+            }
+            let name = column
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join("_")
+                .to_lowercase();
+            fields.insert(name, value);
+        }
+    }
+    fields
+}
+
+// a backslash in front of every ASCII punctuation mark, so a "*" or "#" in a title stays
+// text. & < > " are left to escape_html, a backslash would keep the entity from decoding.
+fn escape_markdown(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len());
+    for c in str.chars() {
+        if c.is_ascii_punctuation() && !matches!(c, '&' | '<' | '>' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_html(str: &str) -> String {
+    str.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// DESCRIPTION_TEMPLATE and SHORT_DESCRIPTION_TEMPLATE in .env, paths to this store's
+// templates. without one the raw vendoo description goes up and the short one stays empty.
+#[derive(Debug, Clone, Default)]
+pub struct DescriptionTemplates {
+    pub description: Option<Template>,
+    pub short_description: Option<Template>,
+}
+
+impl DescriptionTemplates {
+    pub fn from_env() -> Self {
+        Self {
+            description: template_from_env("DESCRIPTION_TEMPLATE"),
+            short_description: template_from_env("SHORT_DESCRIPTION_TEMPLATE"),
+        }
+    }
+
+    pub fn description(&self, product: &VendooProduct) -> String {
+        match &self.description {
+            Some(template) => template.render(product),
+            None => product.description.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn short_description(&self, product: &VendooProduct) -> String {
+        match &self.short_description {
+            Some(template) => template.render(product),
+            None => String::new(),
+        }
+    }
+}
+
+fn template_from_env(var: &str) -> Option<Template> {
+    let path = std::env::var(var).ok()?;
+    match Template::from_file(&path) {
+        Ok(template) => Some(template),
+        Err(e) => {
+            eprintln!("[] couldn't read {}: {}", var, e);
+            None
+        }
+    }
+}
+
+// loaded once, LocalObject::from_vendoo_object renders every product through these
+pub fn description_templates() -> &'static DescriptionTemplates {
+    static TEMPLATES: OnceLock<DescriptionTemplates> = OnceLock::new();
+    TEMPLATES.get_or_init(DescriptionTemplates::from_env)
+}
+
+// hcrelay --preview <sku>, renders one product from CSV_PATH the way it would go up
+pub fn preview(csv_path: &str, sku: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let vendoo = ObjVendoo::from_csv(csv_path)?;
+    let product = vendoo
+        .products
+        .unwrap_or_default()
+        .into_iter()
+        .find(|product| product.sku.as_deref().map(|s| s.trim()) == Some(sku.trim()))
+        .ok_or_else(|| format!("no product with sku {} in {}", sku, csv_path))?;
+
+    let templates = description_templates();
+    let describe = |template: &Option<Template>| match template {
+        Some(template) => template.path.clone(),
+        None => String::from("no template"),
+    };
+
+    Ok(format!(
+        "--- DESCRIPTION ({}) ---\n{}\n\n--- SHORT DESCRIPTION ({}) ---\n{}\n",
+        describe(&templates.description),
+        templates.description(&product),
+        describe(&templates.short_description),
+        templates.short_description(&product),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(columns: serde_json::Value) -> VendooProduct {
+        serde_json::from_value(columns).unwrap()
+    }
+
+    fn render(source: &str, columns: serde_json::Value) -> String {
+        Template::parse(source, false)
+            .unwrap()
+            .render(&product(columns))
+    }
+
+    #[test]
+    fn fields_are_substituted_and_escaped() {
+        let columns = serde_json::json!({
+            "Title": "Levi's 501 <W32>",
+            "Primary Color": "Blue & White",
+            "Description": "line one\nline \"two\"",
+        });
+        assert_eq!(
+            render(
+                "<h2>{{title}}</h2><p>{{ primary_color }}</p>",
+                columns.clone()
+            ),
+            "<h2>Levi's 501 &lt;W32&gt;</h2><p>Blue &amp; White</p>"
+        );
+        assert_eq!(
+            render("{{description}}", columns.clone()),
+            "line one<br>\nline &quot;two&quot;"
+        );
+        assert_eq!(render("[{{brand}}]", columns), "[]");
+    }
+
+    #[test]
+    fn conditionals_treat_missing_and_empty_the_same() {
+        let source = "{{#if brand}}Brand: {{brand}}{{else}}No brand{{/if}}|\
+                      {{#unless condition}}Pre-owned{{/unless}}";
+        assert_eq!(
+            render(source, serde_json::json!({"Brand": "Levi's"})),
+            "Brand: Levi's|Pre-owned"
+        );
+        assert_eq!(
+            render(
+                source,
+                serde_json::json!({"Brand": "  ", "Condition": "New"})
+            ),
+            "No brand|"
+        );
+        assert_eq!(render(source, serde_json::json!({})), "No brand|Pre-owned");
+    }
+
+    #[test]
+    fn blocks_nest() {
+        let source = "{{#if brand}}{{brand}}{{#if primary_color}} in {{primary_color}}\
+                      {{else}}{{#unless condition}} (used){{/unless}}{{/if}}{{/if}}";
+        assert_eq!(
+            render(
+                source,
+                serde_json::json!({"Brand": "Levi's", "Primary Color": "Blue"})
+            ),
+            "Levi's in Blue"
+        );
+        assert_eq!(
+            render(source, serde_json::json!({"Brand": "Levi's"})),
+            "Levi's (used)"
+        );
+        assert_eq!(
+            render(
+                source,
+                serde_json::json!({"Primary Color": "Blue", "Condition": "New"})
+            ),
+            ""
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |source: &str| Template::parse(source, false).unwrap_err();
+        assert_eq!(error("<h2>{{title</h2>"), "unclosed {{");
+        assert_eq!(error("{{title}}{{else}}"), "{{else}} outside of an {{#if}}");
+        assert_eq!(error("{{/if}}"), "{{/if}} without an {{#if}}");
+        assert_eq!(
+            error("{{#if brand}}{{brand}}{{/unless}}"),
+            "brand is missing its {{/if}}"
+        );
+        assert_eq!(
+            error("{{#unless brand}}x{{else}}y{{/if}}"),
+            "brand is missing its {{/unless}}"
+        );
+        assert_eq!(error("{{#if brand}}x"), "brand is missing its {{/if}}");
+        assert_eq!(error("{{two words}}"), "not a field: {{two words}}");
+    }
+
+    #[test]
+    fn markdown_is_rendered_and_field_values_stay_text() {
+        let template = Template::parse(
+            "## {{title}}\n\n{{#if brand}}**Brand:** {{brand}}{{/if}}\n\n- {{condition}}\n",
+            true,
+        )
+        .unwrap();
+        let html = template.render(&product(serde_json::json!({
+            "Title": "# 1. *Rare* [tee](https://x) <b>",
+            "Brand": "A&B_co",
+            "Condition": "`new`",
+        })));
+        assert_eq!(
+            html,
+            "<h2># 1. *Rare* [tee](https://x) &lt;b&gt;</h2>\n\
+             <p><strong>Brand:</strong> A&amp;B_co</p>\n\
+             <ul>\n<li>`new`</li>\n</ul>"
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, watch, Semaphore};
+
+use crate::local::LocalObject;
+use crate::obj_wc::{
+    BatchItemResult, BatchOp, ObjWooCommerce, Rejected, WcApiError, WooCommerceProduct,
+};
+use crate::wc_error::WcError;
+
+// WC_UPLOAD_CONCURRENCY in .env, how many POST /products are in flight at once
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+
+pub fn upload_concurrency() -> usize {
+    match std::env::var("WC_UPLOAD_CONCURRENCY") {
+        Ok(str) => match str.trim().parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!(
+                    "[] WC_UPLOAD_CONCURRENCY {:?} isn't a positive number, using {}",
+                    str, DEFAULT_UPLOAD_CONCURRENCY
+                );
+                DEFAULT_UPLOAD_CONCURRENCY
+            }
+        },
+        Err(_) => DEFAULT_UPLOAD_CONCURRENCY,
+    }
+}
+
+// one product out of an upload, in the order they finish rather than the order they went in
+#[derive(Debug)]
+pub struct UploadResult {
+    pub index: usize, // position in the Vec that was handed over
+    pub sku: String,
+    pub name: String,
+    pub outcome: UploadOutcome,
+}
+
+#[derive(Debug)]
+pub enum UploadOutcome {
+    Created(Box<WooCommerceProduct>),
+    Failed(WcError),
+    Cancelled, // never sent
+}
+
+impl UploadResult {
+    fn new(index: usize, product: &WooCommerceProduct, outcome: UploadOutcome) -> Self {
+        Self {
+            index,
+            sku: product.sku.clone(),
+            name: product.name.clone(),
+            outcome,
+        }
+    }
+
+    // as a batch report line so mark_synced and the sync history take it like any batch.
+    // None for cancelled products, nothing happened to them.
+    pub fn to_batch_item(&self) -> Option<BatchItemResult> {
+        let (id, error) = match &self.outcome {
+            UploadOutcome::Created(product) => (product.id, None),
+            UploadOutcome::Failed(e) => (
+                None,
+                Some(WcApiError {
+                    code: match e {
+                        WcError::Validation(_) => "hcrelay_invalid_product",
+                        _ => e.api_code().unwrap_or("hcrelay_upload_failed"),
+                    }
+                    .to_owned(),
+                    message: e.to_string(),
+                    data: None,
+                }),
+            ),
+            UploadOutcome::Cancelled => return None,
+        };
+        Some(BatchItemResult {
+            op: BatchOp::Create,
+            id,
+            sku: self.sku.clone(),
+            name: self.name.clone(),
+            error,
+        })
+    }
+
+    pub fn debug(&self) -> String {
+        let label = if self.sku.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} (SKU: {})", self.name, self.sku)
+        };
+        match &self.outcome {
+            UploadOutcome::Created(product) => match product.id {
+                Some(id) => format!("uploaded {} -> #{}", label, id),
+                None => format!("uploaded {}", label),
+            },
+            UploadOutcome::Failed(e) => format!("FAILED {}: {}", label, e),
+            UploadOutcome::Cancelled => format!("cancelled {}", label),
+        }
+    }
+}
+
+// the caller's end of an upload. next() hands back results as they complete and None once
+// every product is accounted for. dropping the handle cancels whatever hasn't started.
+#[derive(Debug)]
+pub struct UploadHandle {
+    pub total: usize,
+    results: mpsc::UnboundedReceiver<UploadResult>,
+    rejected: VecDeque<UploadResult>, // failed validation, handed out before the rest
+    cancel: watch::Sender<bool>,
+}
+
+impl UploadHandle {
+    pub async fn next(&mut self) -> Option<UploadResult> {
+        if let Some(result) = self.rejected.pop_front() {
+            return Some(result);
+        }
+        self.results.recv().await
+    }
+
+    // products that haven't been sent yet come back as Cancelled, the ones already in
+    // flight are let finish so we know whether they were created.
+    pub fn cancel(&self) {
+        self.cancel.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancel.borrow()
+    }
+}
+
+impl ObjWooCommerce {
+    // POSTs every product on its own, at most `concurrency` at a time. unlike
+    // batch_products one slow or failing product doesn't hold up the rest, and each result
+    // shows up on the handle as soon as it's in.
+    pub fn upload_concurrently(
+        &self,
+        products: Vec<WooCommerceProduct>,
+        concurrency: usize,
+    ) -> UploadHandle {
+        let store = Arc::new(self.detached());
+        let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        let (sender, results) = mpsc::unbounded_channel();
+        let (cancel, mut cancelled) = watch::channel(false);
+        let total = products.len();
+
+        tokio::spawn(async move {
+            let mut products = products.into_iter().enumerate();
+            while let Some((index, product)) = products.next() {
+                // a closed watch means the handle is gone, same as a cancel
+                let permit = tokio::select! {
+                    biased;
+                    _ = cancelled.wait_for(|cancelled| *cancelled) => None,
+                    permit = permits.clone().acquire_owned() => permit.ok(),
+                };
+                let Some(permit) = permit else {
+                    let _ =
+                        sender.send(UploadResult::new(index, &product, UploadOutcome::Cancelled));
+                    for (index, product) in products.by_ref() {
+                        let _ = sender.send(UploadResult::new(
+                            index,
+                            &product,
+                            UploadOutcome::Cancelled,
+                        ));
+                    }
+                    break;
+                };
+
+                let store = store.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let sku = product.sku.clone();
+                    let name = product.name.clone();
+                    let outcome = match store.post_product(product).await {
+                        Ok(created) => UploadOutcome::Created(Box::new(created)),
+                        Err(e) => UploadOutcome::Failed(e),
+                    };
+                    drop(permit);
+                    let _ = sender.send(UploadResult {
+                        index,
+                        sku,
+                        name,
+                        outcome,
+                    });
+                });
+            }
+        });
+
+        UploadHandle {
+            total,
+            results,
+            rejected: VecDeque::new(),
+            cancel,
+        }
+    }
+
+    // LocalObjects (usually out of LocalSession::compare_wc_vd) the same way. categories,
+    // attributes, tags and media are resolved up front, one at a time, since they grow the
+    // caches on self. products that fail validation come out of the handle first, as
+    // failures.
+    pub async fn upload_local_concurrently(
+        &mut self,
+        objects: Vec<LocalObject>,
+        concurrency: usize,
+    ) -> Result<UploadHandle, WcError> {
+        let (products, rejected) = self.local_to_woocommerce(objects).await?;
+        let sent = products.len();
+        let mut handle = self.upload_concurrently(products, concurrency);
+        handle.total += rejected.len();
+        for (idx, Rejected { product, error }) in rejected.into_iter().enumerate() {
+            handle.rejected.push_back(UploadResult::new(
+                sent + idx,
+                &product,
+                UploadOutcome::Failed(error),
+            ));
+        }
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::retry::RetryPolicy;
+
+    fn store(server: &MockServer) -> ObjWooCommerce {
+        let mut wc =
+            ObjWooCommerce::new_with_auth(server.uri(), String::from("ck"), String::from("cs"));
+        wc.retry = RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        };
+        wc
+    }
+
+    fn product_json(sku: &str) -> serde_json::Value {
+        serde_json::json!({
+            "name": format!("Product {}", sku),
+            "regular_price": "10.00",
+            "description": "",
+            "categories": [],
+            "images": [],
+            "stock_quantity": null,
+            "status": "publish",
+            "sku": sku,
+        })
+    }
+
+    fn products(n: usize) -> Vec<WooCommerceProduct> {
+        (0..n)
+            .map(|i| serde_json::from_value(product_json(&format!("SKU-{}", i))).unwrap())
+            .collect()
+    }
+
+    // every POST /products comes back created as #42 after `delay`
+    async fn created_after(server: &MockServer, delay: Duration) {
+        let mut created = product_json("SKU");
+        created["id"] = serde_json::json!(42);
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(created)
+                    .set_delay(delay),
+            )
+            .mount(server)
+            .await;
+    }
+
+    async fn drain(handle: &mut UploadHandle) -> Vec<UploadResult> {
+        let mut results: Vec<UploadResult> = Vec::new();
+        while let Some(result) = handle.next().await {
+            results.push(result);
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn streams_a_result_per_product() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/wp-json/wc/v3/products"))
+            .and(body_partial_json(serde_json::json!({"sku": "SKU-1"})))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "code": "product_invalid_sku",
+                "message": "Invalid or duplicated SKU.",
+            })))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        created_after(&server, Duration::ZERO).await;
+
+        let mut handle = store(&server).upload_concurrently(products(3), 2);
+        let mut results = drain(&mut handle).await;
+        results.sort_by_key(|result| result.index);
+
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[0].outcome, UploadOutcome::Created(_)));
+        assert!(matches!(results[2].outcome, UploadOutcome::Created(_)));
+        let item = results[1].to_batch_item().unwrap();
+        assert_eq!(item.sku, "SKU-1");
+        assert_eq!(item.error.unwrap().code, "product_invalid_sku");
+    }
+
+    #[tokio::test]
+    async fn runs_at_most_concurrency_at_once() {
+        let server = MockServer::start().await;
+        created_after(&server, Duration::from_millis(300)).await;
+
+        let started = Instant::now();
+        let mut handle = store(&server).upload_concurrently(products(6), 3);
+        let results = drain(&mut handle).await;
+        let elapsed = started.elapsed();
+
+        assert_eq!(results.len(), 6);
+        // two rounds of three, not six one after another and not all at once
+        assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1800), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn cancel_stops_products_not_yet_sent() {
+        let server = MockServer::start().await;
+        created_after(&server, Duration::from_millis(200)).await;
+
+        let mut handle = store(&server).upload_concurrently(products(5), 1);
+        let first = handle.next().await.unwrap();
+        assert!(matches!(first.outcome, UploadOutcome::Created(_)));
+        handle.cancel();
+
+        let rest = drain(&mut handle).await;
+        let cancelled = rest
+            .iter()
+            .filter(|result| matches!(result.outcome, UploadOutcome::Cancelled))
+            .count();
+        assert_eq!(rest.len(), 4);
+        assert!(cancelled >= 3, "{} cancelled", cancelled);
+        assert!(server.received_requests().await.unwrap().len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn invalid_products_come_back_as_failures() {
+        let server = MockServer::start().await;
+        created_after(&server, Duration::ZERO).await;
+
+        let nameless: WooCommerceProduct = serde_json::from_value(product_json("SKU-X")).unwrap();
+        let mut nameless = LocalObject::from_woocommerce_object(&nameless);
+        nameless.name = String::new();
+        let fine = LocalObject::from_woocommerce_object(&products(1)[0]);
+
+        let mut wc = store(&server);
+        let mut handle = wc
+            .upload_local_concurrently(vec![nameless, fine], 2)
+            .await
+            .unwrap();
+        assert_eq!(handle.total, 2);
+        let results = drain(&mut handle).await;
+
+        assert_eq!(results.len(), 2);
+        let item = results[0].to_batch_item().unwrap();
+        assert_eq!(item.sku, "SKU-X");
+        let error = item.error.unwrap();
+        assert_eq!(error.code, "hcrelay_invalid_product");
+        assert!(error.message.contains("has no name"), "{}", error.message);
+        assert!(matches!(results[1].outcome, UploadOutcome::Created(_)));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
@@ -19,6 +19,11 @@ use tokio::runtime::Runtime;
 
 use eframe::egui;
 
+use crate::category_map::CategoryMap;
+use crate::db::is_session_json;
+use crate::local::DelistPolicy;
+use crate::plan::SyncPlan;
+use crate::pricing::PriceRules;
 use crate::state;
 use crate::BasicEnv;
 use crate::{
@@ -73,6 +78,7 @@ pub struct SharedData {
     pub vd: Option<ObjVendoo>,
     pub wc: Option<ObjWooCommerce>,
     pub local_session: Option<LocalSession>,
+    pub previous_session: Option<LocalSession>, // whatever LOCAL_DB held at startup
 
     pub text_buffer: String,
     pub select_mode: SelectMode,
@@ -86,8 +92,29 @@ impl SharedData {
         text_buffer.push_str("Vendoo lib constructed from CSV...\n");
         let mut wc = ObjWooCommerce::new_with_auth(env.wc_url, env.wc_ck, env.wc_sk);
         text_buffer.push_str("WooCommerce obj constructed with auth...\n");
-        wc.fetch_populate_products().await.unwrap();
-        text_buffer.push_str("WooCommerce lib constructed from HTTP...\n");
+        let fetched = wc
+            .fetch_populate_products_with_progress(|progress| {
+                text_buffer.push_str(&format!("{}\n", progress.debug()));
+            })
+            .await;
+        match fetched {
+            Ok(()) => text_buffer.push_str("WooCommerce lib constructed from HTTP...\n"),
+            // the GUI still comes up, everything vendoo-side works without the store
+            Err(e) => text_buffer.push_str(&format!("Couldn't fetch the WooCommerce lib: {}\n", e)),
+        }
+
+        let mut previous_session: Option<LocalSession> = None;
+        if is_session_json(&env.json_path) && std::path::Path::new(&env.json_path).exists() {
+            match LocalSession::load(&env.json_path) {
+                Ok(session) => {
+                    text_buffer.push_str("Previous LocalSession loaded from LOCAL_DB...\n");
+                    previous_session = Some(session);
+                }
+                Err(e) => {
+                    text_buffer.push_str(&format!("Couldn't load LOCAL_DB: {}\n", e));
+                }
+            }
+        }
 
         let select_mode = SelectMode::WC;
         let local_init: bool = false;
@@ -96,6 +123,7 @@ impl SharedData {
             vd: Some(vd),
             wc: Some(wc),
             local_session: None,
+            previous_session,
 
             text_buffer,
             select_mode,
@@ -165,7 +193,7 @@ impl Default for AppState {
             Ok(str) => str,
             Err(_) => String::from(state::CSV_PATH_FAILED),
         };
-        let local_db = env::var("LOCAL_DB").unwrap_or(String::from("no local db!"));
+        let local_db = env::var("LOCAL_DB").unwrap_or(String::from(state::NO_LOCAL_DB));
 
         let env = BasicEnv {
             wc_url: wc_api_url.clone(),
@@ -385,13 +413,37 @@ impl eframe::App for AppState {
                     .push_str("SERIALIZING ALL PRODUCTS INTO LocalSession...\n");
 
                 if !shared.local_init {
-                    shared.local_session = Some(crate::local::LocalSession::from_session(
+                    let mut local_session = crate::local::LocalSession::from_session(
                         shared.wc.clone().unwrap(),
                         shared.vd.clone().unwrap(),
-                    ));
+                    );
+                    PriceRules::from_env().apply(&mut local_session.local_vp);
+                    let category_map = CategoryMap::from_env();
+                    if !category_map.is_empty() {
+                        let report = category_map.apply(&mut local_session);
+                        shared.text_buffer.push_str(&report.debug());
+                    }
+                    if let Some(previous) = &shared.previous_session {
+                        local_session.carry_sync_times(previous);
+                    }
+                    if is_session_json(&self.env.json_path) {
+                        let saved = match local_session.save(&self.env.json_path) {
+                            Ok(()) => format!("LocalSession saved to {}\n", self.env.json_path),
+                            Err(e) => format!("Couldn't save LocalSession: {}\n", e),
+                        };
+                        shared.text_buffer.push_str(&saved);
+                    }
+                    shared.local_session = Some(local_session);
                     shared
                         .text_buffer
-                        .push_str("PRODUCTS SERIALIZED INTO LOCALSESSION!");
+                        .push_str("PRODUCTS SERIALIZED INTO LOCALSESSION!\n");
+                    shared.local_init = true;
+                }
+
+                // dry run only, the GUI never writes to the store
+                if let Some(local_session) = shared.local_session.clone() {
+                    let plan = SyncPlan::build(&local_session, DelistPolicy::from_env());
+                    shared.text_buffer.push_str(&plan.to_table());
                 }
             }
 
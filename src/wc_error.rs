@@ -0,0 +1,217 @@
+use std::fmt;
+
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::obj_wc::WcApiError;
+
+// everything the WooCommerce (and WordPress media) calls can fail with. Display already
+// says what to do about the common ones, so callers can print it as is.
+#[derive(Debug)]
+pub enum WcError {
+    // never got a response: DNS, TLS, refused connection, timeout
+    Transport(reqwest::Error),
+    // got one, but not a 2xx. api is the parsed {code, message, data} body when there is one
+    Http {
+        context: String,
+        status: StatusCode,
+        api: Option<Box<WcApiError>>,
+        body: String,
+    },
+    // a 2xx whose body isn't what we expected, path is where in the JSON it went wrong
+    Deserialize {
+        context: String,
+        path: String,
+        source: serde_json::Error,
+    },
+    // refused before anything was sent
+    Validation(String),
+    // a local file that was supposed to go up (media uploads)
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+impl WcError {
+    pub fn validation(message: impl Into<String>) -> Self {
+        WcError::Validation(message.into())
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            WcError::Http { status, .. } => Some(*status),
+            WcError::Transport(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    // "woocommerce_rest_cannot_view", "term_exists", ...
+    pub fn api_code(&self) -> Option<&str> {
+        match self {
+            WcError::Http { api: Some(api), .. } => Some(api.code.as_str()),
+            _ => None,
+        }
+    }
+
+    // data.resource_id, which is where WooCommerce puts the existing term on term_exists
+    pub fn resource_id(&self) -> Option<u64> {
+        match self {
+            WcError::Http { api: Some(api), .. } => api
+                .data
+                .as_ref()
+                .and_then(|data| data.get("resource_id"))
+                .and_then(|id| id.as_u64()),
+            _ => None,
+        }
+    }
+
+    pub fn is_auth(&self) -> bool {
+        matches!(
+            self.status(),
+            Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN)
+        ) || self
+            .api_code()
+            .is_some_and(|code| code.contains("cannot") || code.contains("authentication"))
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(StatusCode::NOT_FOUND)
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        self.status() == Some(StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        self.status().is_some_and(|status| status.is_server_error())
+    }
+
+    // the next thing to check, shown after the error itself
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            WcError::Transport(e) if e.is_timeout() => {
+                Some("the store took too long to answer, try again")
+            }
+            WcError::Transport(_) => Some("check WC_API_URL and that the store is reachable"),
+            WcError::Deserialize { .. } => {
+                Some("the store answered with something unexpected, is WC_API_URL the site root?")
+            }
+            _ if self.is_auth() => Some(
+                "check WC_CONSUMER_KEY/WC_CONSUMER_SECRET and that the key has read/write access",
+            ),
+            _ if self.is_not_found() => {
+                Some("the endpoint doesn't exist, check WC_API_URL and that WooCommerce is active")
+            }
+            _ if self.is_rate_limited() => Some("the store is rate limiting us, slow down"),
+            _ if self.is_server_error() => Some("the store had an internal error, check its logs"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WcError::Transport(e) => write!(f, "request failed: {}", e)?,
+            WcError::Http {
+                context,
+                status,
+                api,
+                body,
+            } => match api {
+                Some(api) => write!(f, "{}: {} {}: {}", context, status, api.code, api.message)?,
+                None if body.trim().is_empty() => write!(f, "{}: {}", context, status)?,
+                None => write!(f, "{}: {} {}", context, status, truncate(body.trim(), 200))?,
+            },
+            WcError::Deserialize {
+                context,
+                path,
+                source,
+            } => write!(f, "{}: unexpected JSON at {}: {}", context, path, source)?,
+            WcError::Validation(message) => write!(f, "{}", message)?,
+            WcError::Io { path, source } => write!(f, "couldn't read {}: {}", path, source)?,
+        }
+        if let Some(hint) = self.hint() {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WcError::Transport(e) => Some(e),
+            WcError::Deserialize { source, .. } => Some(source),
+            WcError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for WcError {
+    fn from(e: reqwest::Error) -> Self {
+        WcError::Transport(e)
+    }
+}
+
+// the response if it's a 2xx, WcError::Http with the body parsed as far as it goes if not
+pub(crate) async fn check(
+    response: Response,
+    context: impl Into<String>,
+) -> Result<Response, WcError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(WcError::Http {
+        context: context.into(),
+        status,
+        api: serde_json::from_str::<WcApiError>(&body).ok().map(Box::new),
+        body,
+    })
+}
+
+// check, then the body as T
+pub(crate) async fn read_json<T: DeserializeOwned>(
+    response: Response,
+    context: impl Into<String>,
+) -> Result<T, WcError> {
+    let context = context.into();
+    let response = check(response, context.as_str()).await?;
+    let body = response.text().await?;
+    parse_json(&body, context)
+}
+
+pub(crate) fn parse_json<T: DeserializeOwned>(
+    body: &str,
+    context: impl Into<String>,
+) -> Result<T, WcError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| WcError::Deserialize {
+        context: context.into(),
+        path: e.path().to_string(),
+        source: e.into_inner(),
+    })
+}
+
+// same thing for a part of an update patch
+pub(crate) fn from_value<T: DeserializeOwned>(
+    value: serde_json::Value,
+    context: impl Into<String>,
+) -> Result<T, WcError> {
+    serde_path_to_error::deserialize(value).map_err(|e| WcError::Deserialize {
+        context: context.into(),
+        path: e.path().to_string(),
+        source: e.into_inner(),
+    })
+}
+
+fn truncate(str: &str, max: usize) -> String {
+    match str.char_indices().nth(max) {
+        Some((idx, _)) => format!("{}...", &str[..idx]),
+        None => str.to_owned(),
+    }
+}